@@ -1,5 +1,18 @@
+pub mod affixes;
 pub mod conf;
+pub mod hunger;
+pub mod keymap;
+pub mod lighting;
+pub mod loot;
+pub mod mapgen;
+pub mod mods;
+pub mod random;
+pub mod town;
+pub mod traps;
+pub mod travel;
 pub mod ui;
+pub mod uniques;
+pub mod validation;
 
 use tcod::colors::*;
 use tcod::console::*;
@@ -7,80 +20,155 @@ use tcod::input;
 use tcod::input::Event;
 use tcod::map::{FovAlgorithm, Map as FovMap};
 use tcod::input::{Key, Mouse};
+use ui::Camera;
 use ui::inventory_menu;
 use ui::menu;
 use ui::msgbox;
 use ui::render_all;
+use ui::ending_screen;
 use std::cmp;
+use std::collections::HashMap;
 use std::error::Error;
-use std::fs::File;
+use std::fs::{self, File, OpenOptions};
 use std::io::{Read, Write};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use conf::*;
+use affixes::{Effect, Rarity};
+use lighting::{Light, LightMap};
+use mapgen::{Area, MapGenerator, Room};
+use hunger::{Hunger, MAX_SATIETY};
+use keymap::{Action, Keymap};
+use town::{Shop, ShopKind, TOWN_LEVEL};
+use traps::Trap;
+use uniques::UniqueStatus;
 use rand::distributions::{Distribution, WeightedIndex};
 
 pub const PLAYER_ID: usize = 0;
-const MAX_INV_SPACE: usize = 26;
+pub const MAX_INV_SPACE: usize = 26;
 const INVENTORY_WIDTH: i32 = 50;
 
 const FOV_ALGO: FovAlgorithm = FovAlgorithm::Shadow;
 
 const HEAL_AMOUNT: i32 = 12;
+
+// regeneration: each turn the player builds up their max HP times (base + level)
+// points, and heals a hit point for every REGEN_POINTS of them
+const REGEN_BASE: u32 = 4;
+const REGEN_POINTS: u32 = 3000;
 const LIGHTNING_DAMAGE: i32 = 40;
 const LIGHTNING_RANGE: i32 = 5;
 const CONFUSE_RANGE: i32 = 8;
 const CONFUSE_NUM_TURNS: i32 = 10;
 const FIREBALL_DAMAGE: i32 = 24;
 const FIREBALL_RADIUS: i32 = 3;
+const FIREBALL_FLASH_TURNS: u32 = 2;
+const LAVA_DAMAGE: i32 = 8;
+
+// light sources: fuel is counted in turns
+const PLAYER_LIGHT_COLOR: Color = Color { r: 255, g: 210, b: 150 };
+const STARTING_TORCH_FUEL: u32 = 400;
+const STARTING_DAGGER_DURABILITY: u32 = 80;
+const LANTERN_MAX_FUEL: u32 = 1000;
+const OIL_FUEL: u32 = 500;
+const LOW_FUEL_WARNING: u32 = 50;
+
+// durability: percent chance for a hit to wear down each piece of equipment
+// involved, and the condition below which its bonuses start dropping off
+const WEAR_CHANCE: u32 = 30;
+const DEGRADE_CONDITION: u32 = 50;
+
+const MAX_VAULTS_PER_LEVEL: usize = 2;
+const MAX_MAP_ATTEMPTS: u32 = 100;
+const TOWN_LAMP_SPACING: i32 = 10;
+
+/// packs and minions spawn this close to the first of their group
+const PACK_RADIUS: i32 = 2;
+/// how far followers stray from their leader while nothing is going on
+const FOLLOW_DISTANCE: f32 = 3.0;
+
+// the end of the crypt: its ruler, and the crown to bring back to town
+const BOSS_NAME: &str = "Ancient King";
+const ARTIFACT_NAME: &str = "Crown of the Ancient Kings";
+
+const SAVE_FILE: &str = "savegame";
+/// Bumped whenever a change to the game keeps older saves from loading.
+const SAVE_VERSION: u32 = 1;
+/// every finished run is appended here, one JSON record per line
+const SCORES_FILE: &str = "scores";
+const VICTORY_SCORE: u32 = 5000;
 
 // experience and level-ups
 const LEVEL_UP_BASE: u32 = 50;
 const LEVEL_UP_FACTOR: u32 = 150;
 const LEVEL_SCREEN_WIDTH: i32 = 40;
-const CHARACTER_SCREEN_WIDTH: i32 = 30;
+const CHARACTER_SCREEN_WIDTH: i32 = 40;
+const KEYS_SCREEN_WIDTH: i32 = 40;
 
 pub struct Tcod {
     pub root: Root,
     pub con: Offscreen,
     pub panel: Offscreen,
     pub fov: FovMap,
+    pub light_fov: FovMap,
+    pub lighting: LightMap,
     pub key: Key,
     pub mouse: Mouse,
+    pub camera: Camera,
+    /// what the player keeps doing turn after turn, until something interrupts them
+    pub activity: Option<Activity>,
+    pub keymap: Keymap,
+    /// names of the mods the tables were loaded with, in load order
+    pub mods: Vec<String>,
 
     pub tables: Option<TransitionTables>
 }
 
 pub fn init_tcod(game_settings: &GameSettings) -> Tcod {
     let root = Root::initializer()
-        .font("assets/prestige12x12.png", FontLayout::Tcod)
+        .font(&game_settings.font, FontLayout::Tcod)
         .font_type(FontType::Greyscale)
         .size(game_settings.screen_w, game_settings.screen_h)
-        .title("Tcod Tutorial")
+        .title(&game_settings.title)
         .init();
 
-    let con = Offscreen::new(game_settings.map_w, game_settings.map_h);
+    let con = Offscreen::new(game_settings.camera_w, game_settings.camera_h);
     let panel = Offscreen::new(game_settings.screen_w, game_settings.screen_h);
     let fov = FovMap::new(game_settings.map_w, game_settings.map_h);
+    let light_fov = FovMap::new(game_settings.map_w, game_settings.map_h);
+    let lighting = LightMap::new(game_settings.map_w, game_settings.map_h);
+
+    let camera = Camera::new(game_settings.camera_w, game_settings.camera_h);
 
     let tcod = Tcod { root: root, 
-        con: con, panel: panel, fov: fov, key: Default::default(), mouse: Default::default(), tables: None };
+        con: con, panel: panel, fov: fov, light_fov: light_fov, lighting: lighting, key: Default::default(), mouse: Default::default(), camera: camera, activity: None, keymap: Keymap::default(), mods: vec![], tables: None };
 
     tcod::system::set_fps(game_settings.fps_limit);
 
     tcod
 }
 
+impl Tcod {
+    /// whether the player can see a tile: in line of sight and lit by some light
+    pub fn can_see(&self, x: i32, y: i32) -> bool {
+        self.fov.is_in_fov(x, y) && self.lighting.is_lit(x, y)
+    }
+}
+
 fn init_fov(tcod: &mut Tcod, game: &Game){
-    // populate the FOV map, according to the generated map
+    // a loaded game keeps the map size it was saved with, which the settings may no longer match
+    let (map_w, map_h) = (game.game_settings.map_w, game.game_settings.map_h);
+    tcod.fov = FovMap::new(map_w, map_h);
+    tcod.light_fov = FovMap::new(map_w, map_h);
+    tcod.lighting = LightMap::new(map_w, map_h);
+
+    // populate the FOV maps, according to the generated map
     for y in 0..game.game_settings.map_h {
         for x in 0..game.game_settings.map_w {
-            tcod.fov.set(
-                x,
-                y,
-                !game.map[x as usize][y as usize].block_sight,
-                !game.map[x as usize][y as usize].blocked,
-            );
+            let transparent = !game.map[x as usize][y as usize].block_sight();
+            let walkable = !game.map[x as usize][y as usize].blocked();
+            tcod.fov.set(x, y, transparent, walkable);
+            tcod.light_fov.set(x, y, transparent, walkable);
         }
     }
 
@@ -89,6 +177,12 @@ fn init_fov(tcod: &mut Tcod, game: &Game){
 }
 
 pub fn new_game(tcod: &mut Tcod, game_settings: GameSettings) -> (Game, Vec<DisplayObj>){
+    // a seeded run starts from the same dungeon every time, and every level
+    // is built from the seed and its depth
+    if let Some(seed) = game_settings.seed {
+        random::seed(seed);
+    }
+    let seed = game_settings.seed.unwrap_or_else(|| random::rng().gen());
     let mut player = DisplayObj::new(25, 23, '@', "player", WHITE, true);
     player.alive = true;
     player.fighter = Some(Fighter {
@@ -99,17 +193,22 @@ pub fn new_game(tcod: &mut Tcod, game_settings: GameSettings) -> (Game, Vec<Disp
         xp: 0,
         on_death: DeathCallback::Player
     });
-
     let mut objects = vec![player];
 
-    let map: Vec<Vec<Tile>> = make_map(&tcod, &mut objects, &game_settings, 1);
+    let (map, _) = random::for_level(seed, TOWN_LEVEL, || make_map(&tcod, &mut objects, &game_settings, TOWN_LEVEL));
 
     let mut game: Game = Game {
         game_settings: game_settings,
         map: map,
         messages: Messages::new(),
         inventory: vec![],
-        dungeon_level: 1
+        gold: 0,
+        uniques: HashMap::new(),
+        satiety: MAX_SATIETY,
+        regeneration: 0,
+        dungeon_level: TOWN_LEVEL,
+        levels: HashMap::new(),
+        seed: seed
     };
 
     init_fov(tcod, &game);
@@ -119,6 +218,10 @@ pub fn new_game(tcod: &mut Tcod, game_settings: GameSettings) -> (Game, Vec<Disp
         "Welcome stranger! Prepare to perish in the Tombs of the Ancient Kings.",
         RED,
     );
+    game.messages.add(
+        "The town's shops will gladly take your gold before you head down the stairs.",
+        LIGHT_GREY,
+    );
 
     // initial equipment: a dagger
     let mut dagger = DisplayObj::new(0, 0, '-', "dagger", SKY, false);
@@ -129,15 +232,40 @@ pub fn new_game(tcod: &mut Tcod, game_settings: GameSettings) -> (Game, Vec<Disp
         max_hp_bonus: 0,
         defense_bonus: 0,
         power_bonus: 1,
+        light_radius_bonus: 0,
+        fuel: None,
+        durability: STARTING_DAGGER_DURABILITY,
+        max_durability: STARTING_DAGGER_DURABILITY
     });
+    dagger.identified = true;
     game.inventory.push(dagger);
 
+    // and a torch to see by, for a while
+    let mut torch = DisplayObj::new(0, 0, '|', "torch", ORANGE, false);
+    torch.item = Some(Item::Torch);
+    torch.equipment = Some(Equipment {
+        equipped: true,
+        slot: Slot::Light,
+        max_hp_bonus: 0,
+        defense_bonus: 0,
+        power_bonus: 0,
+        light_radius_bonus: 7,
+        fuel: Some(STARTING_TORCH_FUEL),
+        durability: 0,
+        max_durability: 0
+    });
+    torch.identified = true;
+    game.inventory.push(torch);
+    update_player_light(&game, &mut objects);
+
     (game, objects)
 }
 
 pub fn play_game(tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<DisplayObj>){
     // force FOV "recompute" first time through the game loop
-    let mut previous_player_position = (-1, -1);
+    let mut fov_recompute = true;
+    // a new or loaded game starts with the player waiting for orders
+    tcod.activity = None;
 
     while !tcod.root.window_closed() {
         tcod.con.clear();
@@ -148,63 +276,251 @@ pub fn play_game(tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<DisplayObj>
             _ => tcod.key = Default::default(),
         }
 
-        let fov_recompute = previous_player_position != objects[PLAYER_ID].get_pos();
-        render_all(tcod, game, &objects, fov_recompute);
+        // resting goes through turns as fast as it can, and is only drawn once it stops
+        if tcod.activity == Some(Activity::Rest) {
+            if fov_recompute {
+                ui::compute_fov(tcod, game, objects);
+            }
+        } else {
+            render_all(tcod, game, &objects, fov_recompute);
+
+            tcod.root.flush();
+        }
+
+        if let Some(ending) = run_ending(game, objects) {
+            finish_run(tcod, game, objects, ending);
+            break;
+        }
 
-        tcod.root.flush();
         // level up if needed
         level_up(tcod, game, objects);
 
-        previous_player_position = objects[PLAYER_ID].get_pos();
-        let player_action = handle_keys(tcod, game, objects);
+        let previous_player_position = objects[PLAYER_ID].get_pos();
+        let player_action = match tcod.activity {
+            Some(activity) => continue_activity(activity, tcod, game, objects),
+            None => handle_keys(tcod, game, objects)
+        };
         if player_action == PlayerAction::Exit {
             save_game(game, objects).unwrap();
             break;
         }
+        if player_action == PlayerAction::Quit {
+            finish_run(tcod, game, objects, Ending::Quit);
+            break;
+        }
+
+        if player_action == PlayerAction::TookTurn && previous_player_position != objects[PLAYER_ID].get_pos() {
+            traps::spring_traps(tcod, game, objects);
+        }
+
+        if player_action == PlayerAction::TookTurn {
+            loot::drop_loot(tcod, game, objects);
+            burn_fuel(game);
+            hunger::digest(game, objects);
+            regenerate(game, objects);
+        }
+        // swapping or running out of light changes how far the player sees
+        let light_changed = update_player_light(game, objects);
+
+        // moving, opening doors and trampling grass all change what the player can see
+        fov_recompute = player_action == PlayerAction::TookTurn 
+            || previous_player_position != objects[PLAYER_ID].get_pos()
+            || light_changed;
 
-        // let monsters take their turn
+        // let monsters take their turn, several times if the terrain slowed the player down
         if objects[PLAYER_ID].alive && player_action != PlayerAction::DidntTakeTurn {
-            for id in 0..objects.len() {
-                // only if object is not player
-                if objects[id].ai.is_some(){
-                    ai_take_turn( id, &tcod, game, objects);
+            let (player_x, player_y) = objects[PLAYER_ID].get_pos();
+            let turns = if previous_player_position != (player_x, player_y) {
+                game.map[player_x as usize][player_y as usize].kind.move_cost()
+            } else {
+                1
+            };
+
+            for _ in 0..turns {
+                for id in 0..objects.len() {
+                    // only if object is not player
+                    if objects[id].ai.is_some(){
+                        ai_take_turn( id, &tcod, game, objects);
+                    }
                 }
             }
+            burn_in_lava(game, objects);
+            lighting::burn_down(objects);
+
+            if objects[PLAYER_ID].alive {
+                traps::search_passively(tcod, game, objects);
+            }
+        }
+    }
+}
+
+/// How a run came to an end.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Ending {
+    Death,
+    /// made it back to town with the artifact
+    Victory,
+    /// the player gave up on the run
+    Quit
+}
+
+/// A finished run, as recorded in the scores file.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub ending: Ending,
+    pub deepest_level: u32,
+    pub character_level: u32,
+    pub gold: u32,
+    pub score: u32
+}
+
+impl RunRecord {
+    pub fn new(ending: Ending, game: &Game, objects: &[DisplayObj]) -> Self {
+        let deepest_level = game.levels
+            .keys()
+            .copied()
+            .chain(std::iter::once(game.dungeon_level))
+            .max()
+            .unwrap_or(game.dungeon_level);
+        let character_level = objects[PLAYER_ID].level;
+        let xp = objects[PLAYER_ID].fighter.map_or(0, |f| f.xp);
+
+        let mut score = game.gold + xp + deepest_level * 100;
+        if ending == Ending::Victory {
+            score += VICTORY_SCORE;
+        }
+        RunRecord {
+            ending: ending,
+            deepest_level: deepest_level,
+            character_level: character_level,
+            gold: game.gold,
+            score: score
         }
     }
 }
 
+/// Whether the run is over: the player died, or made it back to town with the artifact.
+fn run_ending(game: &Game, objects: &[DisplayObj]) -> Option<Ending> {
+    if !objects[PLAYER_ID].alive {
+        Some(Ending::Death)
+    } else if game.dungeon_level == TOWN_LEVEL 
+            && game.inventory.iter().any(|item| item.item == Some(Item::Artifact)) {
+        Some(Ending::Victory)
+    } else {
+        None
+    }
+}
+
+/// Record a finished run and show the ending. The save is removed, so it can't be continued.
+fn finish_run(tcod: &mut Tcod, game: &Game, objects: &[DisplayObj], ending: Ending) {
+    tcod.activity = None;
+    let record = RunRecord::new(ending, game, objects);
+    if let Err(e) = record_run(&record) {
+        println!("Could not record the run: {}", e);
+    }
+    let _ = fs::remove_file(SAVE_FILE);
+    ending_screen(&record, &mut tcod.root, &game.game_settings);
+}
+
+fn record_run(record: &RunRecord) -> Result<(), Box<dyn Error>> {
+    let record_data = serde_json::to_string(record)?;
+    let mut file = OpenOptions::new().create(true).append(true).open(SCORES_FILE)?;
+    writeln!(file, "{}", record_data)?;
+    Ok(())
+}
+
 fn save_game(game: &Game, objects: &[DisplayObj]) -> Result<(), Box<dyn Error>> {
-    let save_data = serde_json::to_string(&(game, objects))?;
-    let mut file = File::create("savegame")?;
+    let save_data = serde_json::to_string(&(SAVE_VERSION, game, objects))?;
+    let mut file = File::create(SAVE_FILE)?;
     file.write_all(save_data.as_bytes())?;
     Ok(())
 }
 
 fn load_game() -> Result<(Game, Vec<DisplayObj>), Box<dyn Error>> {
     let mut json_save_state = String::new();
-    let mut file = File::open("savegame")?;
+    let mut file = File::open(SAVE_FILE).map_err(|_| "No saved game to load.")?;
     file.read_to_string(&mut json_save_state)?;
-    let result = serde_json
-            ::from_str
-            ::<(Game, Vec<DisplayObj>)>(&json_save_state)?;
 
-    Ok(result)
+    // saves from before they had a version start straight with the game
+    let save_state = serde_json::from_str::<serde_json::Value>(&json_save_state)?;
+    let version = save_state.get(0).and_then(|version| version.as_u64());
+    if version != Some(SAVE_VERSION as u64) {
+        return Err("The saved game is from another version of the game and can't be continued.".into());
+    }
+
+    let (_, game, objects) = serde_json
+            ::from_value
+            ::<(u32, Game, Vec<DisplayObj>)>(save_state)?;
+    Ok((game, objects))
 }
 
 fn next_level(tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<DisplayObj>) {
-    game.messages.add(
-        "You descend down further into the crypt, where will it end...", 
-        RED);
-    
-    let heal_hp = objects[PLAYER_ID].max_hp(game) / 2;
-    objects[PLAYER_ID].heal(heal_hp, &game);
+    // whatever the player was up to, it was on the level they're leaving
+    tcod.activity = None;
+    let level = game.dungeon_level + 1;
+    if change_level(tcod, game, objects, level) {
+        game.messages.add(
+            "You descend down further into the crypt, where will it end...", 
+            RED);
 
-    game.dungeon_level += 1;
-    game.map = make_map(&tcod, objects, &game.game_settings, game.dungeon_level);
+        // only the first visit to a level restores health, so the stairs can't be farmed
+        let heal_hp = objects[PLAYER_ID].max_hp(game) / 2;
+        objects[PLAYER_ID].heal(heal_hp, &game);
+    } else {
+        game.messages.add("You descend back into the crypt.", RED);
+    }
+
+    move_to_stairs(Stairs::Up, objects);
+    init_fov(tcod, game);
+}
+
+fn previous_level(tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<DisplayObj>) {
+    tcod.activity = None;
+    let level = game.dungeon_level - 1;
+    change_level(tcod, game, objects, level);
+    game.messages.add("You climb back up the stairs.", LIGHT_GREY);
+
+    move_to_stairs(Stairs::Down, objects);
     init_fov(tcod, game);
 }
 
+/// Store the current level in the dungeon and switch to another one,
+/// generating it if it was never visited. Returns true for a new level.
+fn change_level(tcod: &Tcod, game: &mut Game, objects: &mut Vec<DisplayObj>, level: u32) -> bool {
+    // NOTE: works only when the player is the first object!
+    let level_objects: Vec<DisplayObj> = objects.drain(PLAYER_ID + 1..).collect();
+    let map = std::mem::take(&mut game.map);
+    game.levels.insert(game.dungeon_level, Level { map: map, objects: level_objects });
+
+    game.dungeon_level = level;
+    match game.levels.remove(&level) {
+        Some(stored) => {
+            game.map = stored.map;
+            objects.extend(stored.objects);
+            false
+        }
+        None => {
+            random::for_level(game.seed, level, || {
+                let (map, areas) = make_map(tcod, objects, &game.game_settings, level);
+                game.map = map;
+                uniques::spawn_uniques(tcod, game, &areas, objects);
+            });
+            true
+        }
+    }
+}
+
+/// place the player on the stairs leading in the given direction, if the level has them
+fn move_to_stairs(direction: Stairs, objects: &mut [DisplayObj]) {
+    let stairs_pos = objects
+        .iter()
+        .find(|object| object.stairs == Some(direction))
+        .map(|object| object.get_pos());
+    if let Some((x, y)) = stairs_pos {
+        objects[PLAYER_ID].set_pos(x, y);
+    }
+}
+
 fn level_up(tcod: &mut Tcod, game: &mut Game, objects: &mut [DisplayObj]) {
     let player = &mut objects[PLAYER_ID];
     let level_up_xp = LEVEL_UP_BASE + player.level * LEVEL_UP_FACTOR;
@@ -260,7 +576,18 @@ fn handle_keys(tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<DisplayObj>)
 
     let player_alive = objects[PLAYER_ID].alive;
 
-    match (tcod.key, tcod.key.text(), player_alive) {
+    // clicking a tile the player knows walks them there
+    if tcod.mouse.lbutton_pressed && player_alive {
+        tcod.mouse.lbutton_pressed = false;
+        if let Some((x, y)) = tcod.camera.to_world(tcod.mouse.cx as i32, tcod.mouse.cy as i32) {
+            if x >= 0 && y >= 0 && x < game.game_settings.map_w && y < game.game_settings.map_h
+                    && game.map[x as usize][y as usize].explored {
+                return start_activity(Activity::Travel { x: x, y: y }, tcod, game, objects);
+            }
+        }
+    }
+
+    match (tcod.key, tcod.keymap.action(&tcod.key), player_alive) {
         
         (Key {
             code: Enter,
@@ -276,44 +603,30 @@ fn handle_keys(tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<DisplayObj>)
         }
         (Key { code: Escape, .. },
                 _,
-                _) => return Exit, // exit game
-        // movement keys
-        (Key { code: Up, .. }, _, true) | (Key { code: NumPad8, .. }, _, true) => {
-            player_move_or_attack(0, -1, game, objects);
-            TookTurn
-        }
-        (Key { code: Down, .. }, _, true) | (Key { code: NumPad2, .. }, _, true) => {
-            player_move_or_attack(0, 1, game, objects);
-            TookTurn
-        }
-        (Key { code: Left, .. }, _, true) | (Key { code: NumPad4, .. }, _, true) => {
-            player_move_or_attack(-1, 0, game, objects);
-            TookTurn
-        }
-        (Key { code: Right, .. }, _, true) | (Key { code: NumPad6, .. }, _, true) => {
-            player_move_or_attack(1, 0, game, objects);
-            TookTurn
-        }
-        (Key { code: Home, .. }, _, true) | (Key { code: NumPad7, .. }, _, true) => {
-            player_move_or_attack(-1, -1, game, objects);
-            TookTurn
-        }
-        (Key { code: PageUp, .. }, _, true) | (Key { code: NumPad9, .. }, _, true) => {
-            player_move_or_attack(1, -1, game, objects);
-            TookTurn
-        }
-        (Key { code: End, .. }, _, true) | (Key { code: NumPad1, .. }, _, true) => {
-            player_move_or_attack(-1, 1, game, objects);
-            TookTurn
-        }
-        (Key { code: PageDown, .. }, _, true) | (Key { code: NumPad3, .. }, _, true) => {
-            player_move_or_attack(1, 1, game, objects);
-            TookTurn
+                _) => {
+            let choice = menu(
+                "",
+                &["Save and exit", "Abandon this run", "Keep playing"],
+                LEVEL_SCREEN_WIDTH,
+                &mut tcod.root,
+                &game.game_settings
+            );
+            return match choice {
+                Some(0) => Exit,
+                Some(1) => Quit,
+                _ => DidntTakeTurn
+            };
         }
-        (Key { code: NumPad5, .. }, _, true) => {
+        (_, Some(Action::Wait), true) => {
             TookTurn // do nothing, i.e. wait for the monster to come to you
         },
-        (Key { code: Text, .. }, "g", true) => {
+        // movement keys
+        (_, Some(action), true) if action.direction().is_some() => {
+            let (dx, dy) = action.direction().unwrap();
+            player_move_or_attack(dx, dy, tcod, game, objects);
+            TookTurn
+        },
+        (_, Some(Action::PickUp), true) => {
             // pick up an item
             let item_id = objects
                 .iter()
@@ -323,7 +636,7 @@ fn handle_keys(tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<DisplayObj>)
             }
             DidntTakeTurn
         },
-        (Key { code: Text, .. }, "i", true) => {
+        (_, Some(Action::Inventory), true) => {
             // show the inventory
             let inv_index = inventory_menu(
                 &game.inventory,
@@ -336,7 +649,7 @@ fn handle_keys(tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<DisplayObj>)
             }
             TookTurn
         },
-        (Key { code: Text, .. }, "d", true) => {
+        (_, Some(Action::Drop), true) => {
             // show the inventory; if an item is selected, drop it
             let inventory_index = inventory_menu(
                 &game.inventory,
@@ -349,17 +662,55 @@ fn handle_keys(tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<DisplayObj>)
             }
             DidntTakeTurn
         },
-        (Key { code: Text, .. }, "<", true) => {
-            // go down stairs, if the player is on them
-            let player_on_stairs = objects
-                .iter()
-                .any(|object| object.get_pos() == objects[PLAYER_ID].get_pos() && object.name == "stairs");
-            if player_on_stairs {
+        (_, Some(Action::Descend), true) => {
+            // go down stairs if the player is on them, otherwise head for them
+            if player_on_stairs(Stairs::Down, objects) {
                 next_level(tcod, game, objects);
+                DidntTakeTurn
+            } else {
+                travel_to_stairs(Stairs::Down, tcod, game, objects)
             }
-            DidntTakeTurn
         },
-        (Key { code: Text, .. }, "c", true) => {
+        (_, Some(Action::Ascend), true) => {
+            // go up stairs if the player is on them, otherwise head for them
+            if player_on_stairs(Stairs::Up, objects) {
+                previous_level(tcod, game, objects);
+                DidntTakeTurn
+            } else {
+                travel_to_stairs(Stairs::Up, tcod, game, objects)
+            }
+        },
+        (_, Some(Action::Explore), true) => {
+            // explore the level automatically
+            let items_seen = items_in_view(tcod, objects);
+            start_activity(Activity::Explore { items_seen: items_seen }, tcod, game, objects)
+        },
+        (_, Some(Action::Rest), true) => {
+            // rest until healed
+            start_resting(tcod, game, objects)
+        },
+        (_, Some(Action::Search), true) => {
+            // look for traps and secret doors
+            traps::search_actively(tcod, game, objects);
+            TookTurn
+        },
+        (_, Some(Action::Disarm), true) => {
+            // disarm a known trap next to the player
+            if traps::disarm(tcod, game, objects) {
+                TookTurn
+            } else {
+                DidntTakeTurn
+            }
+        },
+        (_, Some(Action::CloseDoor), true) => {
+            // close an open door next to the player
+            if close_door(tcod, game, objects) {
+                TookTurn
+            } else {
+                DidntTakeTurn
+            }
+        },
+        (_, Some(Action::Character), true) => {
             // show character information
             let player = &objects[PLAYER_ID];
             let level = player.level;
@@ -374,20 +725,54 @@ Experience to level up: {}
 
 Maximum HP: {}
 Attack: {}
-Defense: {}",
-    level, fighter.xp, level_up_xp, player.max_hp(game), player.power(game), player.defense(game)
+Defense: {}
+
+Equipment:
+{}",
+    level, fighter.xp, level_up_xp, player.max_hp(game), player.power(game), player.defense(game),
+    equipment_summary(game)
 );
                 msgbox(&msg, CHARACTER_SCREEN_WIDTH, &mut tcod.root, &game.game_settings);
             }
         
             DidntTakeTurn
         },
+        (_, Some(Action::Help), true) => {
+            // show what each key does
+            msgbox(&tcod.keymap.reference(), KEYS_SCREEN_WIDTH, &mut tcod.root, &game.game_settings);
+            DidntTakeTurn
+        },
 
         _ => return DidntTakeTurn
     }
     
 }
 
+/// one line per equipped item, with its condition when it can wear out
+fn equipment_summary(game: &Game) -> String {
+    let lines: Vec<String> = game.inventory
+        .iter()
+        .filter_map(|item| {
+            let equipment = item.equipment.filter(|e| e.equipped)?;
+            Some(match equipment.condition() {
+                Some(condition) => format!("{}: {} ({}%)", equipment.slot, item.display_name(), condition),
+                None => format!("{}: {}", equipment.slot, item.display_name())
+            })
+        })
+        .collect();
+    if lines.is_empty() {
+        "nothing".into()
+    } else {
+        lines.join("\n")
+    }
+}
+
+fn player_on_stairs(direction: Stairs, objects: &[DisplayObj]) -> bool {
+    objects
+        .iter()
+        .any(|object| object.get_pos() == objects[PLAYER_ID].get_pos() && object.stairs == Some(direction))
+}
+
 /// Returns a value that depends on level. the table specifies what
 /// value occurs after each level, default is 0.
 fn from_dungeon_level(table: &[Transition], level: u32) -> u32 {
@@ -398,6 +783,40 @@ fn from_dungeon_level(table: &[Transition], level: u32) -> u32 {
         .map_or(0, |transition| transition.value)
 }
 
+/// Returns the map generator configured for a level, the plain
+/// rooms-and-corridors layout when none is configured.
+fn generator_for_level(table: &[GeneratorTransition], level: u32) -> MapGenerator {
+    table
+        .iter()
+        .rev()
+        .find(|transition| level >= transition.level)
+        .map_or(MapGenerator::Rooms, |transition| transition.generator)
+}
+
+/// Check that every generator the tables use can lay out a level with the
+/// game settings, the rooms generator too when it's used before the first entry.
+pub fn check_generators(tables: &TransitionTables, game_settings: &GameSettings) -> Result<(), String> {
+    let mut generators: Vec<MapGenerator> = vec![];
+    if tables.map_generators.iter().all(|transition| transition.level > 1) {
+        generators.push(MapGenerator::Rooms);
+    }
+    for transition in &tables.map_generators {
+        if !generators.contains(&transition.generator) {
+            generators.push(transition.generator);
+        }
+    }
+
+    let errors: Vec<String> = generators
+        .into_iter()
+        .filter_map(|generator| mapgen::check_generator(generator, game_settings).err())
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DisplayObj {
     pub x: i32,
@@ -408,21 +827,65 @@ pub struct DisplayObj {
     pub blocks: bool,
     pub alive: bool,
     pub always_visible: bool,
+    #[serde(default)]
+    pub hidden: bool,
     pub fighter: Option<Fighter>,
     pub ai: Option<Ai>,
     pub item: Option<Item>,
     pub level: u32,
+    #[serde(default)]
+    pub stairs: Option<Stairs>,
+    #[serde(default)]
+    pub trap: Option<Trap>,
+    #[serde(default)]
+    pub light: Option<Light>,
+    /// worth in gold: the price of an item, the size of a gold pile or what a monster carries
+    #[serde(default)]
+    pub value: u32,
+    #[serde(default)]
+    pub shop: Option<Shop>,
+    /// a named monster that only turns up once per run
+    #[serde(default)]
+    pub unique: bool,
+    /// shown instead of the usual message when it dies
+    #[serde(default)]
+    pub death_message: Option<String>,
+    /// items dropped where it dies
+    #[serde(default)]
+    pub loot: Vec<DisplayObj>,
+    /// loot table rolled when it dies, or when it's opened for a container
+    #[serde(default)]
+    pub drops: Option<LootDrop>,
+    /// a chest, sarcophagus or the like that can be opened
+    #[serde(default)]
+    pub container: bool,
+    #[serde(default)]
+    pub rarity: Rarity,
+    /// what an enchanted weapon does on every hit
+    #[serde(default)]
+    pub effects: Vec<Effect>,
+    /// name of the curse on a piece of equipment, which can't be taken off while it lasts
+    #[serde(default)]
+    pub curse: Option<String>,
+    /// whether the player knows if the item is cursed
+    #[serde(default)]
+    pub identified: bool,
+    /// how much eating it fills the player up
+    #[serde(default)]
+    pub nutrition: u32,
     equipment: Option<Equipment>
 }
 
 impl DisplayObj {
     pub fn new(x: i32, y: i32, char: char, name: &str, color: Color, blocks: bool) -> Self{
-        DisplayObj {x: x, y: y, char: char, color: color, name: name.into(), blocks: blocks, alive: false, always_visible: false, fighter: None, ai: None, item: None, level: 1, equipment: None}
+        DisplayObj {x: x, y: y, char: char, color: color, name: name.into(), blocks: blocks, alive: false, always_visible: false, hidden: false, fighter: None, ai: None, item: None, level: 1, stairs: None, trap: None, light: None, value: 0, shop: None, unique: false, death_message: None, loot: vec![], drops: None, container: false, rarity: Rarity::Common, effects: vec![], curse: None, identified: false, nutrition: 0, equipment: None}
     }
 
-    pub fn draw(&self, con: &mut dyn Console){
-        con.set_default_foreground(self.color);
-        con.put_char(self.x, self.y, self.char, BackgroundFlag::None);
+    pub fn draw(&self, con: &mut dyn Console, camera: &Camera){
+        if let Some((x, y)) = camera.to_screen(self.x, self.y) {
+            con.set_default_foreground(self.color);
+            con.put_char(x, y, self.char, BackgroundFlag::None);
+        }
     }
 
     pub fn get_pos(&self) -> (i32, i32) {
@@ -473,7 +936,31 @@ impl DisplayObj {
                 self.name, target.name, damage),
                 WHITE
             );
-            if let Some(xp) = target.take_damage(damage, game){
+            let mut xp = target.take_damage(damage, game);
+
+            // hits wear down the player's weapons when dealt, and armour when taken
+            if self.name == "player" {
+                wear_equipment(game, |e| e.power_bonus > 0);
+            }
+            if target.name == "player" {
+                wear_equipment(game, |e| e.defense_bonus > 0 || e.max_hp_bonus > 0);
+            }
+
+            // enchanted equipment adds its own touch, as long as the target still stands
+            for effect in self.get_equipped_effects(game) {
+                match effect {
+                    Effect::Burning { damage } if xp.is_none() => {
+                        game.messages.add(format!("{} is scorched for {} hit points.", target.name, damage), ORANGE);
+                        xp = target.take_damage(damage, game);
+                    }
+                    Effect::Drain { amount } => {
+                        game.messages.add(format!("{} drains {} hit points.", self.name, amount), LIGHT_VIOLET);
+                        self.heal(amount, game);
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(xp) = xp {
                 self.fighter.as_mut().unwrap().xp += xp;
             }
         } else {
@@ -512,6 +999,11 @@ impl DisplayObj {
                     format!("Equipped {} on {}.", self.name, equipment.slot),
                     LIGHT_GREEN,
                 );
+                // putting it on is how the player finds out about a curse
+                self.identified = true;
+                if self.curse.is_some() {
+                    messages.add(format!("The {} is cursed! It clings to you.", self.name), RED);
+                }
             }
         } else {
             messages.add(
@@ -521,17 +1013,23 @@ impl DisplayObj {
         }
     }
 
-    /// Dequip object and show a message about it
-    pub fn dequip(&mut self, messages: &mut Messages) {
+    /// Dequip object and show a message about it. Returns false if it
+    /// stays on, because it's cursed.
+    pub fn dequip(&mut self, messages: &mut Messages) -> bool {
         if self.item.is_none() {
             messages.add(
                 format!("Can't dequip {:?} because it's not an Item.", self),
                 RED,
             );
-            return;
+            return true;
         };
         if let Some(ref mut equipment) = self.equipment {
             if equipment.equipped {
+                if self.curse.is_some() {
+                    self.identified = true;
+                    messages.add(format!("You can't remove the {}, it's cursed!", self.name), RED);
+                    return false;
+                }
                 equipment.equipped = false;
                 messages.add(
                     format!("Dequipped {} from {}.", self.name, equipment.slot),
@@ -544,6 +1042,15 @@ impl DisplayObj {
                 RED,
             );
         }
+        true
+    }
+
+    /// name as the player knows it, with the curse once it's been found out
+    pub fn display_name(&self) -> String {
+        match self.curse {
+            Some(ref curse) if self.identified => format!("{} {}", self.name, curse),
+            _ => self.name.clone()
+        }
     }
 
     pub fn max_hp(&self, game: &Game) -> i32 {
@@ -551,7 +1058,7 @@ impl DisplayObj {
         let bonus: i32 = self
             .get_all_equipped(game)
             .iter()
-            .map(|e| e.max_hp_bonus)
+            .map(|e| e.effective(e.max_hp_bonus))
             .sum();
         base_max_hp + bonus
     }
@@ -561,10 +1068,10 @@ impl DisplayObj {
         let bonus: i32 = self
                 .get_all_equipped(game)
                 .iter()
-                .map(|e| e.power_bonus)
+                .map(|e| e.effective(e.power_bonus))
                 .sum();
 
-        base_power + bonus
+        base_power + bonus - self.hunger(game).power_penalty()
     }
 
     pub fn defense(&self, game: &Game) -> i32 {
@@ -572,9 +1079,30 @@ impl DisplayObj {
         let bonus: i32 = self
             .get_all_equipped(game)
             .iter()
-            .map(|e| e.defense_bonus)
+            .map(|e| e.effective(e.defense_bonus))
+            .sum();
+        base_defense + bonus - self.hunger(game).defense_penalty()
+    }
+
+    /// only the player goes hungry
+    fn hunger(&self, game: &Game) -> Hunger {
+        if self.name == "player" {
+            Hunger::from_satiety(game.satiety)
+        } else {
+            Hunger::Fed
+        }
+    }
+
+    /// how far the player's light reaches: a faint glimmer, plus whatever
+    /// equipped light sources still have fuel
+    pub fn light_radius(&self, game: &Game) -> i32 {
+        let bonus: i32 = self
+            .get_all_equipped(game)
+            .iter()
+            .filter(|e| e.fuel != Some(0))
+            .map(|e| e.light_radius_bonus)
             .sum();
-        base_defense + bonus
+        (game.game_settings.base_light_radius + bonus).max(0)
     }
 
     /// returns a list of equipped items
@@ -589,6 +1117,31 @@ impl DisplayObj {
             vec![] // other objects have no equipment
         }
     }
+
+    /// returns the on-hit effects of all equipped items
+    pub fn get_equipped_effects(&self, game: &Game) -> Vec<Effect> {
+        if self.name == "player" {
+            game.inventory
+                .iter()
+                .filter(|item| item.equipment.map_or(false, |e| e.equipped))
+                .flat_map(|item| item.effects.iter().copied())
+                .collect()
+        } else {
+            vec![]
+        }
+    }
+}
+
+/// Something the player does over many turns without being asked again.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Activity {
+    /// wait until healed
+    Rest,
+    /// head for the nearest unexplored part of the level, stopping for any
+    /// item that comes into view beyond those already seen
+    Explore { items_seen: usize },
+    /// walk to a known tile
+    Travel { x: i32, y: i32 }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -596,6 +1149,8 @@ pub enum PlayerAction {
     TookTurn,
     DidntTakeTurn,
     Exit,
+    /// give up on the run for good
+    Quit,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -604,6 +1159,43 @@ pub enum Ai {
     Confused {
         previous_ai: Box<Ai>,
         num_turns: i32,
+    },
+    /// heads for the player even when out of sight, e.g. after an alarm
+    Alerted {
+        previous_ai: Box<Ai>,
+        num_turns: i32,
+    },
+    /// fights like a basic monster, with a pack of followers
+    Leader {
+        pack: u32,
+    },
+    /// sticks close to its pack's leader, and closes in on the player once the leader spots them
+    Follower {
+        pack: u32,
+    }
+}
+
+impl Ai {
+    /// whether this AI leads the given pack, even while confused
+    fn leads(&self, pack_id: u32) -> bool {
+        match self {
+            Ai::Leader { pack } => *pack == pack_id,
+            Ai::Confused { previous_ai, .. } | Ai::Alerted { previous_ai, .. } => previous_ai.leads(pack_id),
+            _ => false
+        }
+    }
+
+    /// This AI once an alarm has gone off: the monster makes for the player,
+    /// and goes back to fighting the way it did when it gets there.
+    fn alerted(self, num_turns: i32) -> Ai {
+        match self {
+            Ai::Confused { previous_ai, num_turns: confused_turns } => Ai::Confused {
+                previous_ai: Box::new(previous_ai.alerted(num_turns)),
+                num_turns: confused_turns
+            },
+            Ai::Alerted { previous_ai, .. } => Ai::Alerted { previous_ai: previous_ai, num_turns: num_turns },
+            ai => Ai::Alerted { previous_ai: Box::new(ai), num_turns: num_turns }
+        }
     }
 }
 
@@ -622,6 +1214,7 @@ pub struct Fighter {
 pub enum DeathCallback {
     Player,
     Monster,
+    Boss,
 }
 
 impl DeathCallback {
@@ -629,12 +1222,20 @@ impl DeathCallback {
         use DeathCallback::*;
         let callback: fn(&mut DisplayObj, game: &mut Game) = match self {
             Player => player_death,
-            Monster => monster_death
+            Monster => monster_death,
+            Boss => boss_death
         };
         callback(object, game);
     }
 }
 
+/// Stairs connect a level to the one above or below it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Stairs {
+    Up,
+    Down
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Item {
     Heal,
@@ -643,7 +1244,21 @@ pub enum Item {
     Fireball,
     Sword,
     Shield,
-    Helmet
+    Helmet,
+    Key,
+    /// restores a piece of equipment to full durability
+    Repair,
+    /// lifts the curses on everything the player carries
+    RemoveCurse,
+    /// rations, corpses and anything else the player can eat
+    Food,
+    Gold,
+    /// the crown of the Ancient Kings, carried back to town to win
+    Artifact,
+    Torch,
+    Lantern,
+    /// refills a lantern
+    Oil
 }
 
 enum UseResult {
@@ -659,7 +1274,37 @@ pub struct Equipment {
     pub equipped: bool,
     pub max_hp_bonus: i32,
     pub power_bonus: i32,
-    pub defense_bonus: i32
+    pub defense_bonus: i32,
+    #[serde(default)]
+    pub light_radius_bonus: i32,
+    /// turns left before a light source burns out, None if it needs no fuel
+    #[serde(default)]
+    pub fuel: Option<u32>,
+    #[serde(default)]
+    pub durability: u32,
+    /// 0 for equipment that never wears out
+    #[serde(default)]
+    pub max_durability: u32
+}
+
+impl Equipment {
+    /// condition as a percentage, None for equipment that never wears out
+    pub fn condition(&self) -> Option<u32> {
+        if self.max_durability == 0 {
+            None
+        } else {
+            Some(self.durability * 100 / self.max_durability)
+        }
+    }
+
+    /// A bonus as the equipment gives it in its current condition: full
+    /// strength until it's badly worn, then dropping off with it.
+    pub fn effective(&self, bonus: i32) -> i32 {
+        match self.condition() {
+            Some(condition) if condition < DEGRADE_CONDITION => bonus * condition as i32 / DEGRADE_CONDITION as i32,
+            _ => bonus
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
@@ -667,6 +1312,7 @@ pub enum Slot {
     LeftHand,
     RightHand,
     Head,
+    Light,
 }
 
 impl std::fmt::Display for Slot {
@@ -675,44 +1321,151 @@ impl std::fmt::Display for Slot {
             Slot::LeftHand => write!(f, "left hand"),
             Slot::RightHand => write!(f, "right hand"),
             Slot::Head => write!(f, "head"),
+            Slot::Light => write!(f, "light"),
+        }
+    }
+}
+
+/// The terrain of a map tile, which decides how it looks and how it can be crossed.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TileKind {
+    Wall,
+    Floor,
+    DoorClosed,
+    DoorOpen,
+    DoorLocked,
+    /// looks like a wall until someone finds it
+    SecretDoor,
+    ShallowWater,
+    Lava,
+    Rubble,
+    Grass,
+    TallGrass
+}
+
+impl TileKind {
+    pub fn blocks(&self) -> bool {
+        use TileKind::*;
+        matches!(self, Wall | DoorClosed | DoorLocked | SecretDoor)
+    }
+
+    pub fn blocks_sight(&self) -> bool {
+        use TileKind::*;
+        matches!(self, Wall | DoorClosed | DoorLocked | SecretDoor | TallGrass)
+    }
+
+    pub fn is_door(&self) -> bool {
+        use TileKind::*;
+        matches!(self, DoorClosed | DoorOpen | DoorLocked | SecretDoor)
+    }
+
+    /// tiles that hurt whoever stands in them; monsters won't step into these
+    pub fn is_hazard(&self) -> bool {
+        *self == TileKind::Lava
+    }
+
+    /// whether a safe path may cross this tile, opening doors on the way
+    pub fn is_traversable(&self) -> bool {
+        use TileKind::*;
+        !matches!(self, Wall | DoorLocked | Lava)
+    }
+
+    /// how many turns it takes to walk onto this tile
+    pub fn move_cost(&self) -> u32 {
+        use TileKind::*;
+        match self {
+            ShallowWater | Rubble => 2,
+            _ => 1
+        }
+    }
+
+    /// the character drawn on the tile and its color, if any
+    pub fn glyph(&self) -> Option<(char, Color)> {
+        use TileKind::*;
+        match self {
+            Wall | SecretDoor | Floor => None,
+            DoorClosed => Some(('+', Color { r: 160, g: 110, b: 50 })),
+            DoorOpen => Some(('\'', Color { r: 160, g: 110, b: 50 })),
+            DoorLocked => Some(('+', GOLD)),
+            ShallowWater => Some(('~', LIGHT_AZURE)),
+            Lava => Some(('~', YELLOW)),
+            Rubble => Some((',', GREY)),
+            Grass => Some(('"', DARK_GREEN)),
+            TallGrass => Some(('"', LIGHT_GREEN))
+        }
+    }
+
+    /// background color when in view, or when only remembered
+    pub fn background(&self, game_settings: &GameSettings, visible: bool) -> Color {
+        use TileKind::*;
+        match (self, visible) {
+            (Wall, true) | (SecretDoor, true) => game_settings.light_wall_color,
+            (Wall, false) | (SecretDoor, false) => game_settings.dark_wall_color,
+            (ShallowWater, true) => Color { r: 40, g: 60, b: 140 },
+            (ShallowWater, false) => Color { r: 20, g: 30, b: 70 },
+            (Lava, true) => Color { r: 200, g: 70, b: 20 },
+            (Lava, false) => Color { r: 90, g: 35, b: 10 },
+            (_, true) => game_settings.light_ground_color,
+            (_, false) => game_settings.dark_ground_color
         }
     }
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Tile {
-    pub blocked: bool,
-    pub block_sight: bool,
+    pub kind: TileKind,
     pub explored: bool
 }
 
 impl Tile{
-    pub fn empty() -> Self {
+    pub fn new(kind: TileKind) -> Self {
         Tile {
-            blocked: false,
-            block_sight: false,
+            kind: kind,
             explored: false
         }
     }
 
+    pub fn empty() -> Self {
+        Tile::new(TileKind::Floor)
+    }
+
     pub fn wall() -> Self {
-        Tile {
-            blocked: true,
-            block_sight: true,
-            explored: false
-        }
+        Tile::new(TileKind::Wall)
+    }
+
+    pub fn blocked(&self) -> bool {
+        self.kind.blocks()
+    }
+
+    pub fn block_sight(&self) -> bool {
+        self.kind.blocks_sight()
     }
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+/// Screen layout, map size and other settings, read from the "game" section
+/// of the settings file. Anything left out keeps its default.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GameSettings{
+    /// the window title
+    pub title: String,
+    /// path of the font image, which also sets the size of a tile
+    pub font: String,
+    /// seed for the dungeon and everything else that's rolled, random when not set
+    pub seed: Option<u64>,
     pub screen_w: i32,
     pub screen_h: i32,
     pub map_w: i32,
     pub map_h: i32,
+    pub camera_w: i32,
+    pub camera_h: i32,
     pub room_max_size: u32,
     pub room_min_size: u32,
     pub max_rooms: i32,
+    pub min_rooms: usize,
+    pub min_stairs_distance: i32,
+    /// the deepest dungeon level, where the boss waits
+    pub final_level: u32,
     pub max_room_monsters: i32,
     pub dark_wall_color: Color,
     pub light_wall_color: Color,
@@ -720,7 +1473,9 @@ pub struct GameSettings{
     pub light_ground_color: Color,
     pub fps_limit: i32,
     pub fov_light_walls: bool,
-    pub torch_radius: i32,
+    pub sight_radius: i32,
+    /// how far the player sees in the dark without a light source
+    pub base_light_radius: i32,
     pub bar_w: i32,
     pub panel_h: i32,
     pub panel_y: i32,
@@ -729,16 +1484,30 @@ pub struct GameSettings{
     pub msg_h: i32
 }
 
+impl Default for GameSettings {
+    fn default() -> Self {
+        GameSettings::new()
+    }
+}
+
 impl GameSettings {
     pub fn new () -> Self {
         GameSettings {
+            title: "Tcod Tutorial".into(),
+            font: "assets/prestige12x12.png".into(),
+            seed: None,
             screen_w: 80,
             screen_h: 50,
-            map_w: 80,
-            map_h: 43,
+            map_w: 120,
+            map_h: 80,
+            camera_w: 80,
+            camera_h: 43,
             room_max_size: 10,
             room_min_size: 6,
-            max_rooms: 20,
+            max_rooms: 50,
+            min_rooms: 4,
+            min_stairs_distance: 20,
+            final_level: 10,
             max_room_monsters: 6,
             dark_wall_color: Color {r: 35, g: 35, b: 35 },
             light_wall_color: Color {r: 55, g: 55, b: 55 },
@@ -746,7 +1515,8 @@ impl GameSettings {
             light_ground_color: Color {r: 100, g: 90,b: 70 },
             fps_limit: 20,
             fov_light_walls: true,
-            torch_radius: 10,
+            sight_radius: 20,
+            base_light_radius: 1,
             bar_w: 20,
             panel_h: 7,
             panel_y: 43,
@@ -755,183 +1525,436 @@ impl GameSettings {
             msg_h: 6
         }
     }
-}
 
-pub type Map = Vec<Vec<Tile>>;
+    /// Check that the settings describe a screen and map the game can
+    /// actually use, listing every problem found.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors = vec![];
 
-#[derive(Serialize, Deserialize)] 
-pub struct Game {
-    pub game_settings: GameSettings,
-    pub map: Map,
-    pub messages: Messages,
-    pub inventory: Vec<DisplayObj>,
-    dungeon_level: u32
-}
+        if self.room_min_size < 1 || self.room_min_size > self.room_max_size {
+            errors.push(format!(
+                "room_min_size ({}) must be at least 1 and no larger than room_max_size ({})",
+                self.room_min_size, self.room_max_size
+            ));
+        }
+        // the BSP generator needs a room and its walls to fit in the map
+        let room_space = self.room_max_size as i32 + 2;
+        if self.map_w < room_space || self.map_h < room_space {
+            errors.push(format!(
+                "map size {}x{} is too small for rooms of up to {} tiles (needs at least {}x{})",
+                self.map_w, self.map_h, self.room_max_size, room_space, room_space
+            ));
+        }
+        // the town needs a ring of wall around it, so nobody walks off the map
+        if self.map_w < mapgen::TOWN_W + 2 || self.map_h < mapgen::TOWN_H + 2 {
+            errors.push(format!(
+                "map size {}x{} is too small for the town and the wall around it (needs at least {}x{})",
+                self.map_w, self.map_h, mapgen::TOWN_W + 2, mapgen::TOWN_H + 2
+            ));
+        }
+        if self.max_rooms < 1 || (self.max_rooms as usize) < self.min_rooms {
+            errors.push(format!(
+                "max_rooms ({}) must be at least 1 and at least min_rooms ({})",
+                self.max_rooms, self.min_rooms
+            ));
+        }
+        if self.min_stairs_distance as f32 >= ((self.map_w.pow(2) + self.map_h.pow(2)) as f32).sqrt() {
+            errors.push(format!(
+                "min_stairs_distance ({}) can never be reached on a {}x{} map",
+                self.min_stairs_distance, self.map_w, self.map_h
+            ));
+        }
+        if self.final_level < 1 {
+            errors.push(format!("final_level ({}) must be at least 1", self.final_level));
+        }
+        if self.camera_w < 1 || self.camera_h < 1 || self.camera_w > self.screen_w || self.camera_h > self.panel_y {
+            errors.push(format!(
+                "camera size {}x{} must fit on the screen above the panel ({}x{})",
+                self.camera_w, self.camera_h, self.screen_w, self.panel_y
+            ));
+        }
+        if self.panel_y + self.panel_h > self.screen_h {
+            errors.push(format!(
+                "panel (y {} + height {}) runs off the bottom of the screen ({})",
+                self.panel_y, self.panel_h, self.screen_h
+            ));
+        }
+        if self.msg_x + self.msg_w > self.screen_w || self.bar_w > self.msg_x {
+            errors.push(format!(
+                "message log (x {} + width {}) and HP bar (width {}) must fit side by side in the screen width ({})",
+                self.msg_x, self.msg_w, self.bar_w, self.screen_w
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+}
+
+pub type Map = Vec<Vec<Tile>>;
+
+/// A level the player is not currently on, kept so it can be revisited.
+#[derive(Serialize, Deserialize)]
+pub struct Level {
+    pub map: Map,
+    pub objects: Vec<DisplayObj>
+}
 
-#[derive(Clone, Copy, Debug)]
-pub struct Room {
-    x1: i32,
-    y1: i32,
-    x2: i32,
-    y2: i32
+#[derive(Serialize, Deserialize)] 
+pub struct Game {
+    pub game_settings: GameSettings,
+    pub map: Map,
+    pub messages: Messages,
+    pub inventory: Vec<DisplayObj>,
+    #[serde(default)]
+    pub gold: u32,
+    /// uniques that have turned up this run, so they never spawn twice
+    #[serde(default)]
+    pub uniques: HashMap<String, UniqueStatus>,
+    /// how much the player has left to digest before going hungry
+    #[serde(default = "hunger::full_satiety")]
+    pub satiety: u32,
+    /// regeneration built up towards the next hit point healed
+    #[serde(default)]
+    pub regeneration: u32,
+    dungeon_level: u32,
+    /// the levels visited this run, other than the one the player is on
+    #[serde(default)]
+    levels: HashMap<u32, Level>,
+    /// the levels of a run are built from this and their depth
+    #[serde(default)]
+    seed: u64
 }
 
-impl Room {
-    pub fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
-        Room {
-            x1: x,
-            y1: y,
-            x2: x + w,
-            y2: y + h,
+/// Generate a level, returning its map along with the areas objects were spawned in.
+fn make_map(tcod: &Tcod, objects: &mut Vec<DisplayObj>, game_settings: &GameSettings, level: u32) -> (Map, Vec<Area>) {
+    // Player is the first element, remove everything else.
+    // NOTE: works only when the player is the first object!
+    assert_eq!(&objects[PLAYER_ID] as *const _, &objects[0] as *const _);
+
+    if level == TOWN_LEVEL {
+        return make_town(tcod, objects, game_settings);
+    }
+
+    let generator = tcod.tables
+        .as_ref()
+        .map_or(MapGenerator::Rooms, |tables| generator_for_level(&tables.map_generators, level));
+
+    // keep generating until the layout is playable
+    let mut attempts = 0;
+    let (map, areas) = loop {
+        objects.truncate(1);
+        let (mut map, mut areas) = mapgen::generate(generator, game_settings);
+        place_vaults(tcod, &mut map, &mut areas, level, objects);
+        mapgen::decorate(&mut map, &areas, level);
+
+        attempts += 1;
+        match mapgen::validate(&map, &areas, game_settings) {
+            Ok(()) => break (map, areas),
+            // rather than stop the run, settle for a plain layout that always connects
+            Err(_) if attempts >= MAX_MAP_ATTEMPTS => {
+                objects.truncate(1);
+                break mapgen::fallback(game_settings);
+            }
+            Err(_) => {}
         }
+    };
+
+    let (start_x, start_y) = areas[0].center;
+    objects[PLAYER_ID].set_pos(start_x, start_y);
+
+    // the deepest level has no way further down, only the boss in the last area
+    let (last_area_x, last_area_y) = areas[areas.len() - 1].center;
+    if level == game_settings.final_level {
+        place_boss(tcod, last_area_x, last_area_y, objects);
     }
 
-    pub fn center(&self) -> (i32, i32) {
-        let center_x = (self.x1 + self.x2) /2;
-        let center_y = (self.y1 + self.y2) /2;
-        (center_x, center_y)
+    //Don't place monsters in the first area
+    for area in &areas[1..] {
+        place_objects(tcod, area, &map, level, objects);
     }
 
-    pub fn intersects_with(&self, other: &Room) -> bool {
-        (self.x1 <= other.x2)
-            && (self.x2 >= other.x1)
-            && (self.y1 <= other.y2)
-            && (self.y2 >= other.y1)
+    // create stairs back up where the player arrives, and down at the center of the last area
+    if level > TOWN_LEVEL {
+        objects.push(make_stairs(start_x, start_y, Stairs::Up));
     }
-}
 
-fn create_room(room: Room, map: &mut Map) {
-    // go through the tiles in the rectangle and make them passable
-    for x in (room.x1 + 1)..room.x2 {
-        for y in (room.y1 + 1)..room.y2 {
-            map[x as usize][y as usize] = Tile::empty();
-        }
+    if level < game_settings.final_level {
+        objects.push(make_stairs(last_area_x, last_area_y, Stairs::Down));
     }
+
+    (map, areas)
 }
 
-fn create_h_tunnel(x1: i32, x2: i32, y: i32, map: &mut Map) {
-    // horizontal tunnel. `min()` and `max()` are used in case `x1 > x2`
-    for x in cmp::min(x1, x2)..(cmp::max(x1, x2) + 1) {
-        map[x as usize][y as usize] = Tile::empty();
+/// The Ancient King, standing on the artifact he guards.
+fn place_boss(tcod: &Tcod, x: i32, y: i32, objects: &mut Vec<DisplayObj>) {
+    let tables = match tcod.tables.as_ref() {
+        Some(tables) => tables,
+        None => return
+    };
+    if let Some(artifact) = tables.find_item(ARTIFACT_NAME) {
+        objects.push(artifact.as_object(x, y));
+    }
+    if let Some(boss) = tables.find_monster(BOSS_NAME) {
+        objects.push(boss.as_object(x, y));
     }
 }
 
-fn create_v_tunnel(y1: i32, y2: i32, x: i32, map: &mut Map) {
-    // vertical tunnel
-    for y in cmp::min(y1, y2)..(cmp::max(y1, y2) + 1) {
-        map[x as usize][y as usize] = Tile::empty();
+/// The town above the crypt: a shop for each kind of shopkeeper, lamp
+/// posts along the square and the stairs down on its east side.
+fn make_town(tcod: &Tcod, objects: &mut Vec<DisplayObj>, game_settings: &GameSettings) -> (Map, Vec<Area>) {
+    objects.truncate(1);
+    let (map, areas) = mapgen::town(ShopKind::ALL.len(), game_settings);
+    let (square_x, square_y) = areas[0].center;
+    objects[PLAYER_ID].set_pos(square_x, square_y);
+
+    for (kind, shop) in ShopKind::ALL.iter().zip(&areas[1..]) {
+        objects.push(town::shopkeeper(*kind, shop.center, tcod.tables.as_ref()));
     }
-}
 
-fn make_map(tcod: &Tcod, objects: &mut Vec<DisplayObj>, game_settings: &GameSettings, level: u32) -> Map {
-    let mut map = vec![vec![Tile::wall(); game_settings.map_h as usize]; game_settings.map_w as usize];
+    for offset in [-TOWN_LAMP_SPACING * 2, -TOWN_LAMP_SPACING, TOWN_LAMP_SPACING, TOWN_LAMP_SPACING * 2] {
+        objects.push(town::lamp_post(square_x + offset, square_y - 1));
+    }
 
-    // Player is the first element, remove everything else.
-    // NOTE: works only when the player is the first object!
-    assert_eq!(&objects[PLAYER_ID] as *const _, &objects[0] as *const _);
-    objects.truncate(1);
+    let stairs = areas[0].tiles
+        .iter()
+        .filter(|&&(_, y)| y == square_y)
+        .max_by_key(|&&(x, _)| x)
+        .copied()
+        .unwrap_or((square_x + 1, square_y));
+    objects.push(make_stairs(stairs.0, stairs.1, Stairs::Down));
 
-    let mut rooms: Vec<Room> = vec![];
-    for _ in 0..game_settings.max_rooms {
-        let w = rand::thread_rng().gen_range(game_settings.room_min_size..game_settings.room_max_size+1) as i32;
-        let h = rand::thread_rng().gen_range(game_settings.room_min_size..game_settings.room_max_size+1) as i32;
+    (map, areas)
+}
 
-        let x = rand::thread_rng().gen_range(0..game_settings.map_w - w );
-        let y = rand::thread_rng().gen_range(0..game_settings.map_h - h);
+/// Stamp prefab vaults allowed at this depth into the map and spawn the
+/// monsters and items from their legends. Vault tiles are taken out of the
+/// spawn areas, so random monsters don't wander into them.
+fn place_vaults(tcod: &Tcod, map: &mut Map, areas: &mut Vec<Area>, level: u32, objects: &mut Vec<DisplayObj>) {
+    let tables = match tcod.tables.as_ref() {
+        Some(tables) => tables,
+        None => return
+    };
 
-        let new_room = Room::new(x, y, w, h);
+    let mut placed: Vec<Room> = vec![];
+    for template in &tables.vaults {
+        if placed.len() >= MAX_VAULTS_PER_LEVEL {
+            break;
+        }
+        if level < template.min_level || level > template.max_level 
+                || random::rng().gen_range(0..100) >= template.chance {
+            continue;
+        }
 
-        // run through the other rooms and see if they intersect with this one
-        let failed = rooms
-            .iter()
-            .any(|other_room| new_room.intersects_with(other_room));
+        let (w, h) = (template.width(), template.height());
+        let (vault_x, vault_y) = match mapgen::find_vault_spot(map, areas, &placed, w, h) {
+            Some(spot) => spot,
+            None => continue
+        };
+        let bounds = Room::new(vault_x - 1, vault_y - 1, w + 1, h + 1);
+
+        let mut entrances = vec![];
+        for (row_y, row) in template.rows.iter().enumerate() {
+            for (row_x, glyph) in row.chars().enumerate() {
+                let (x, y) = (vault_x + row_x as i32, vault_y + row_y as i32);
+                let tile = match glyph {
+                    ' ' => continue,
+                    '#' => Tile::wall(),
+                    '.' => Tile::empty(),
+                    '+' => {
+                        entrances.push((x, y));
+                        Tile::new(TileKind::DoorClosed)
+                    }
+                    _ => {
+                        let legend = template.legend_for(glyph);
+                        let monster = legend
+                            .and_then(|entry| entry.monster.as_ref())
+                            .and_then(|name| tables.find_monster(name));
+                        let item = legend
+                            .and_then(|entry| entry.item.as_ref())
+                            .and_then(|name| tables.find_item(name));
+                        objects.extend(monster.map(|monster| monster.as_object(x, y)));
+                        if let Some(item) = item {
+                            // treasure guarded in a vault may come enchanted, like any other
+                            let mut item = item.as_object(x, y);
+                            affixes::enchant(&mut item, tables, level);
+                            objects.push(item);
+                        }
+
+                        let kind = legend.and_then(|entry| entry.tile).unwrap_or(TileKind::Floor);
+                        if kind.is_door() {
+                            entrances.push((x, y));
+                        }
+                        Tile::new(kind)
+                    }
+                };
+                map[x as usize][y as usize] = tile;
+            }
+        }
 
-        if !failed {
-            create_room(new_room, &mut map);
-            
-            let (new_x, new_y) = new_room.center();
+        for area in areas.iter_mut() {
+            area.tiles.retain(|&tile| !bounds.contains(tile));
+        }
 
-            if rooms.is_empty() {
-                objects[PLAYER_ID].set_pos(new_x, new_y);
+        if entrances.is_empty() {
+            entrances.push((vault_x + w / 2, vault_y + h / 2));
+        }
+        for (x, y) in entrances {
+            // start digging just outside the entrance, so the tunnel doesn't run along the vault wall
+            let outside = if y == vault_y {
+                (x, y - 1)
+            } else if y == vault_y + h - 1 {
+                (x, y + 1)
+            } else if x == vault_x {
+                (x - 1, y)
+            } else if x == vault_x + w - 1 {
+                (x + 1, y)
             } else {
-                //Don't place monsters in the first room
-                place_objects(tcod, new_room, &map, level, objects);
-                let (prev_x, prev_y) = rooms[rooms.len() - 1].center();
-
-                if rand::random() {
-                    create_h_tunnel(prev_x, new_x, prev_y, &mut map);
-                    create_v_tunnel(prev_y, new_y, new_x, &mut map);
-                } else {
-                    create_v_tunnel(prev_y, new_y, prev_x, &mut map);
-                    create_h_tunnel(prev_x, new_x, new_y, &mut map);
-                    
-                }
-            }
-            rooms.push(new_room);
+                (x, y)
+            };
+            mapgen::connect_to_nearest_area(outside, bounds, areas, map);
         }
+        areas.retain(|area| !area.tiles.is_empty());
+        placed.push(bounds);
     }
+}
 
-    // create stairs at the center of the last room
-    let (last_room_x, last_room_y) = rooms[rooms.len() - 1].center();
-    let mut stairs = DisplayObj::new(last_room_x, last_room_y, '<', "stairs", WHITE, false);
+fn make_stairs(x: i32, y: i32, direction: Stairs) -> DisplayObj {
+    let mut stairs = match direction {
+        Stairs::Up => DisplayObj::new(x, y, '<', "stairs up", WHITE, false),
+        Stairs::Down => DisplayObj::new(x, y, '>', "stairs down", WHITE, false)
+    };
     stairs.always_visible = true;
-    objects.push(stairs);
-
-    map
+    stairs.stairs = Some(direction);
+    stairs
 }
 
-fn place_objects(tcod: &Tcod, room: Room, map: &Map, level: u32, objects: &mut Vec<DisplayObj>) {
+fn place_objects(tcod: &Tcod, area: &Area, map: &Map, level: u32, objects: &mut Vec<DisplayObj>) {
+    // without spawn tables there's nothing to place
+    let tables = match tcod.tables.as_ref() {
+        Some(tables) => tables,
+        None => return
+    };
+
     // maximum number of monsters per room
     let max_spawn = from_dungeon_level(
-        &tcod.tables.as_ref().unwrap().max_monsters,
+        &tables.max_monsters,
         level,
     );
     generate_objects(max_spawn, 
-        &tcod.tables.as_ref().unwrap().monsters, 
-        room, map, level, objects);
+        &tables.monsters, 
+        area, map, level, objects);
     
     
-    // maximum number of items per room
+    // maximum number of items per room, equipment may come enchanted
     let max_spawn = from_dungeon_level(
-        &tcod.tables.as_ref().unwrap().max_items,
+        &tables.max_items,
         level,
     );
+    let first_item = objects.len();
     generate_objects(max_spawn, 
-        &tcod.tables.as_ref().unwrap().items, 
-        room, map, level, objects);
+        &tables.items, 
+        area, map, level, objects);
+    for item in &mut objects[first_item..] {
+        affixes::enchant(item, tables, level);
+    }
+
+    // maximum number of traps per room
+    let max_spawn = from_dungeon_level(
+        &tables.max_traps,
+        level,
+    );
+    generate_objects(max_spawn, 
+        &tables.traps, 
+        area, map, level, objects);
+
+    // maximum number of features (braziers, glowing fungus...) per room
+    let max_spawn = from_dungeon_level(
+        &tables.max_features,
+        level,
+    );
+    generate_objects(max_spawn, 
+        &tables.features, 
+        area, map, level, objects);
 }
 
 fn generate_objects(max_spawns: u32, conf_data: &Vec<ObjectConfiguration>, 
-        room: Room, map: &Map, 
+        area: &Area, map: &Map, 
         level: u32, objects: &mut Vec<DisplayObj>){
     
     // choose random number of monsters
-    let num_to_spawn = rand::thread_rng().gen_range(0..max_spawns + 1);
-
-    let mut weights: Vec<u32> = Vec::new();
-
-    for data in conf_data {
-        weights.push(from_dungeon_level(&data.transition_table, level));
-    }
-
-    let choices = WeightedIndex::new(weights).unwrap();
-
-    let mut rng = rand::thread_rng();
+    let num_to_spawn = random::rng().gen_range(0..max_spawns + 1);
 
     for _ in 0..num_to_spawn {
-        let x = rand::thread_rng().gen_range(room.x1+1..room.x2);
-        let y = rand::thread_rng().gen_range(room.y1+1..room.y2);
+        let (x, y) = area.random_tile();
 
-        if is_blocked(x, y, map, objects) {
+        if is_blocked(x, y, map, objects) || map[x as usize][y as usize].kind.is_hazard() {
             continue;
         }
 
-        let object_data = &conf_data[choices.sample(&mut rng)];
+        if let Some(object_data) = choose_from_table(conf_data, level) {
+            // levels are checked before features go in, so a chest mustn't close off a tunnel
+            if object_data.container && loot::cuts_passage(x, y, map, objects) {
+                continue;
+            }
+            match object_data.pack.as_ref() {
+                Some(pack) => spawn_pack(object_data, pack, x, y, conf_data, map, objects),
+                None => objects.push(object_data.as_object(x, y))
+            }
+        }
+    }
+}
 
-        objects.push(object_data.as_object(x, y));
+/// Spawn a monster with the rest of its pack around it: either more of the
+/// same kind, or followers looked up in the same table with it as their leader.
+fn spawn_pack(first: &ObjectConfiguration, pack: &Pack, x: i32, y: i32, 
+        conf_data: &[ObjectConfiguration], map: &Map, objects: &mut Vec<DisplayObj>) {
+    let size = random::rng().gen_range(pack.min_size..cmp::max(pack.min_size, pack.max_size) + 1);
+    let mut leader = first.as_object(x, y);
+
+    let (follower_data, follower_ai) = match pack.followers.as_ref() {
+        Some(followers) => {
+            let pack_id = random::rng().gen();
+            leader.ai = Some(Ai::Leader { pack: pack_id });
+            (conf_data.iter().find(|data| data.name() == followers), Some(Ai::Follower { pack: pack_id }))
+        }
+        None => (Some(first), None)
+    };
+    objects.push(leader);
+
+    if let Some(follower_data) = follower_data {
+        let tiles = free_tiles_around(x, y, PACK_RADIUS, size.saturating_sub(1), map, objects);
+        for (follower_x, follower_y) in tiles {
+            let mut follower = follower_data.as_object(follower_x, follower_y);
+            if follower_ai.is_some() {
+                follower.ai = follower_ai.clone();
+            }
+            objects.push(follower);
+        }
     }
 }
 
+/// Pick a random entry from a spawn table, weighted for the dungeon level.
+/// Returns None when nothing in the table can appear at this level.
+fn choose_from_table(conf_data: &[ObjectConfiguration], level: u32) -> Option<&ObjectConfiguration> {
+    choose_matching(conf_data, level, |_| true)
+}
+
+/// Like `choose_from_table`, but only entries passing the filter can be picked.
+fn choose_matching<F>(conf_data: &[ObjectConfiguration], level: u32, filter: F) -> Option<&ObjectConfiguration>
+        where F: Fn(&ObjectConfiguration) -> bool {
+    let weights: Vec<u32> = conf_data
+        .iter()
+        .map(|data| if filter(data) { from_dungeon_level(&data.transition_table, level) } else { 0 })
+        .collect();
+
+    let choices = WeightedIndex::new(weights).ok()?;
+    Some(&conf_data[choices.sample(&mut random::rng())])
+}
+
 fn move_towards(id: usize, target_x: i32, target_y: i32, map: &Map, objects: &mut [DisplayObj]){
     let dx = target_x - objects[id].x;
     let dy = target_y - objects[id].y;
@@ -939,7 +1962,18 @@ fn move_towards(id: usize, target_x: i32, target_y: i32, map: &Map, objects: &mu
 
     let dx = (dx as f32 / distance).round() as i32;
     let dy = (dy as f32 / distance).round() as i32;
-    move_by(id, dx, dy, map, objects);
+
+    // if the direct step is blocked or dangerous, try to slide around it
+    for (step_x, step_y) in [(dx, dy), (dx, 0), (0, dy)] {
+        if (step_x, step_y) == (0, 0) {
+            continue;
+        }
+        let (x, y) = (objects[id].x + step_x, objects[id].y + step_y);
+        if !is_blocked(x, y, map, objects) && !map[x as usize][y as usize].kind.is_hazard() {
+            objects[id].set_pos(x, y);
+            return;
+        }
+    }
 }
 
 /// Mutably borrow two *separate* elements from the given slice.
@@ -964,15 +1998,25 @@ pub fn ai_take_turn(monster_id: usize, tcod: &Tcod, game: &mut Game, objects: &m
                 previous_ai,
                 num_turns,
             } => ai_confused(monster_id, tcod, game, objects, previous_ai, num_turns),
+            Alerted {
+                previous_ai,
+                num_turns,
+            } => ai_alerted(monster_id, tcod, game, objects, previous_ai, num_turns),
+            Leader { pack } => {
+                ai_basic(monster_id, tcod, game, objects);
+                Leader { pack: pack }
+            }
+            Follower { pack } => ai_follower(monster_id, tcod, game, objects, pack),
         };
         objects[monster_id].ai = Some(new_ai);
     }
 }
 
 fn ai_basic(monster_id: usize, tcod: &Tcod, game: &mut Game, objects: &mut [DisplayObj]) -> Ai {
-    // a basic monster takes its turn. If you can see it, it can see you
+    // a basic monster takes its turn when it can see the player: in line of
+    // sight, and not hidden in the dark
     let (monster_x, monster_y) = objects[monster_id].get_pos();
-    if tcod.fov.is_in_fov(monster_x, monster_y) {
+    if monster_sees_player(monster_x, monster_y, tcod, objects) {
         if objects[monster_id].distance_to(&objects[PLAYER_ID]) >= 2.0 {
             // move towards player if far away
             let (player_x, player_y) = objects[PLAYER_ID].get_pos();
@@ -986,6 +2030,11 @@ fn ai_basic(monster_id: usize, tcod: &Tcod, game: &mut Game, objects: &mut [Disp
     Ai::Basic
 }
 
+fn monster_sees_player(monster_x: i32, monster_y: i32, tcod: &Tcod, objects: &[DisplayObj]) -> bool {
+    let (player_x, player_y) = objects[PLAYER_ID].get_pos();
+    tcod.fov.is_in_fov(monster_x, monster_y) && tcod.lighting.is_lit(player_x, player_y)
+}
+
 fn ai_confused(monster_id: usize, _tcod: &Tcod, game: &mut Game, objects: &mut [DisplayObj], 
         previous_ai: Box<Ai>, num_turns: i32) -> Ai {
     if num_turns >= 0 {
@@ -993,8 +2042,8 @@ fn ai_confused(monster_id: usize, _tcod: &Tcod, game: &mut Game, objects: &mut [
         // move in a random direction, and decrease the number of turns confused
         move_by(
             monster_id,
-            rand::thread_rng().gen_range(-1..2),
-            rand::thread_rng().gen_range(-1..2),
+            random::rng().gen_range(-1..2),
+            random::rng().gen_range(-1..2),
             &game.map,
             objects,
         );
@@ -1012,6 +2061,52 @@ fn ai_confused(monster_id: usize, _tcod: &Tcod, game: &mut Game, objects: &mut [
     }
 }
 
+fn ai_alerted(monster_id: usize, tcod: &Tcod, game: &mut Game, objects: &mut [DisplayObj],
+        previous_ai: Box<Ai>, num_turns: i32) -> Ai {
+    // once the player is in sight, or the monster loses interest, fight as it did before
+    let (monster_x, monster_y) = objects[monster_id].get_pos();
+    if num_turns <= 0 || monster_sees_player(monster_x, monster_y, tcod, objects) {
+        objects[monster_id].ai = Some(*previous_ai);
+        ai_take_turn(monster_id, tcod, game, objects);
+        return objects[monster_id].ai.take().unwrap_or(Ai::Basic);
+    }
+
+    let (player_x, player_y) = objects[PLAYER_ID].get_pos();
+    move_towards(monster_id, player_x, player_y, &game.map, objects);
+    Ai::Alerted {
+        previous_ai: previous_ai,
+        num_turns: num_turns - 1,
+    }
+}
+
+fn ai_follower(monster_id: usize, tcod: &Tcod, game: &mut Game, objects: &mut [DisplayObj], pack: u32) -> Ai {
+    let (monster_x, monster_y) = objects[monster_id].get_pos();
+    if monster_sees_player(monster_x, monster_y, tcod, objects) {
+        ai_basic(monster_id, tcod, game, objects);
+        return Ai::Follower { pack: pack };
+    }
+
+    // without a leader the pack scatters, each fighting on its own
+    let leader_id = objects
+        .iter()
+        .position(|object| object.alive && object.ai.as_ref().map_or(false, |ai| ai.leads(pack)));
+    let leader_id = match leader_id {
+        Some(leader_id) => leader_id,
+        None => return Ai::Basic
+    };
+
+    // once the leader has seen the player the whole pack closes in,
+    // otherwise the followers keep close to it
+    let (leader_x, leader_y) = objects[leader_id].get_pos();
+    if monster_sees_player(leader_x, leader_y, tcod, objects) {
+        let (player_x, player_y) = objects[PLAYER_ID].get_pos();
+        move_towards(monster_id, player_x, player_y, &game.map, objects);
+    } else if objects[monster_id].distance_to(&objects[leader_id]) > FOLLOW_DISTANCE {
+        move_towards(monster_id, leader_x, leader_y, &game.map, objects);
+    }
+    Ai::Follower { pack: pack }
+}
+
 fn player_death(player: &mut DisplayObj, game: &mut Game) {
     // the game ended!
     game.messages.add("You died!", RED);
@@ -1021,19 +2116,35 @@ fn player_death(player: &mut DisplayObj, game: &mut Game) {
     player.color = DARK_RED;
 }
 
+fn boss_death(boss: &mut DisplayObj, game: &mut Game) {
+    game.messages.add(
+        format!("With a last howl the {} falls. His crown is yours to take back to the surface!", boss.name),
+        LIGHT_VIOLET,
+    );
+    monster_death(boss, game);
+}
+
 fn monster_death(monster: &mut DisplayObj, game: &mut Game) {
     // transform it into a nasty corpse! it doesn't block, can't be
     // attacked and doesn't move
     // transform it into a nasty corpse! it doesn't block, can't be
     // attacked and doesn't move
-    game.messages.add(
-        format!(
-            "{} is dead! You gain {} experience points.",
-            monster.name,
-            monster.fighter.unwrap().xp
-        ),
-        ORANGE,
-    );
+    match monster.death_message.as_ref() {
+        Some(death_message) => game.messages.add(death_message.clone(), LIGHT_VIOLET),
+        None => game.messages.add(format!("{} is dead!", monster.name), ORANGE)
+    }
+    game.messages.add(format!("You gain {} experience points.", monster.fighter.unwrap().xp), ORANGE);
+    if monster.unique {
+        game.uniques.insert(monster.name.clone(), UniqueStatus::Slain);
+    }
+    if monster.value > 0 {
+        game.messages.add(format!("You find {} gold on the {}.", monster.value, monster.name), GOLD);
+        game.gold += monster.value;
+        monster.value = 0;
+    }
+    // a necromancer isn't picky about what they eat
+    monster.nutrition = monster.fighter.map_or(0, |f| f.base_max_hp as u32) * hunger::CORPSE_NUTRITION;
+    monster.item = Some(Item::Food);
     monster.char = '%';
     monster.color = DARK_RED;
     monster.blocks = false;
@@ -1050,28 +2161,93 @@ fn move_by(id: usize, dx: i32, dy: i32, map: &Map, objects: &mut [DisplayObj]){
     }
 }
 
-pub fn player_move_or_attack( dx: i32, dy: i32, game: &mut Game, objects: &mut [DisplayObj]) {
+pub fn player_move_or_attack( dx: i32, dy: i32, tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<DisplayObj>) {
     // the coordinates the player is moving to/attacking
     let x = objects[PLAYER_ID].x + dx;
     let y = objects[PLAYER_ID].y + dy;
 
     // try to find an attackable object there
     let target_id = objects.iter().position(|object| object.fighter.is_some() && object.get_pos() == (x, y));
+    let keeper_id = objects.iter().position(|object| object.shop.is_some() && object.get_pos() == (x, y));
+    let container_id = objects.iter().position(|object| object.container && object.get_pos() == (x, y));
 
-    // attack if target found, move otherwise
-    match target_id {
-        Some(target_id) => {
+    // attack if target found, trade with shopkeepers, open containers, move otherwise
+    match (target_id, keeper_id, container_id) {
+        (Some(target_id), _, _) => {
             let (player, target) = mut_two(PLAYER_ID, target_id, objects);
             player.attack(target, game);
         }
-        None => {
-            move_by(PLAYER_ID, dx, dy, &game.map, objects);
+        (None, Some(keeper_id), _) => town::trade(keeper_id, tcod, game, objects),
+        (None, None, Some(container_id)) => loot::open_container(container_id, tcod, game, objects),
+        (None, None, None) => match game.map[x as usize][y as usize].kind {
+            TileKind::DoorClosed => {
+                game.messages.add("You open the door.", LIGHT_GREY);
+                set_tile(x, y, TileKind::DoorOpen, tcod, &mut game.map);
+            }
+            TileKind::DoorLocked => {
+                let key_id = game.inventory.iter().position(|item| item.item == Some(Item::Key));
+                match key_id {
+                    Some(key_id) => {
+                        let key = game.inventory.remove(key_id);
+                        game.messages.add(format!("You unlock the door with the {}.", key.name), LIGHT_GREY);
+                        set_tile(x, y, TileKind::DoorOpen, tcod, &mut game.map);
+                    }
+                    None => game.messages.add("The door is locked.", LIGHT_GREY)
+                }
+            }
+            _ => {
+                move_by(PLAYER_ID, dx, dy, &game.map, objects);
+
+                // walking through tall grass flattens it
+                let (player_x, player_y) = objects[PLAYER_ID].get_pos();
+                if game.map[player_x as usize][player_y as usize].kind == TileKind::TallGrass {
+                    set_tile(player_x, player_y, TileKind::Grass, tcod, &mut game.map);
+                }
+            }
+        }
+    }
+}
+
+/// change the terrain of a tile, keeping the FOV map in sync
+fn set_tile(x: i32, y: i32, kind: TileKind, tcod: &mut Tcod, map: &mut Map) {
+    map[x as usize][y as usize].kind = kind;
+    tcod.fov.set(x, y, !kind.blocks_sight(), !kind.blocks());
+    tcod.light_fov.set(x, y, !kind.blocks_sight(), !kind.blocks());
+}
+
+/// close the first open door next to the player, unless something stands in it
+fn close_door(tcod: &mut Tcod, game: &mut Game, objects: &[DisplayObj]) -> bool {
+    let (player_x, player_y) = objects[PLAYER_ID].get_pos();
+    for dx in -1..2 {
+        for dy in -1..2 {
+            let (x, y) = (player_x + dx, player_y + dy);
+            let occupied = objects.iter().any(|object| object.get_pos() == (x, y));
+            if game.map[x as usize][y as usize].kind == TileKind::DoorOpen && !occupied {
+                set_tile(x, y, TileKind::DoorClosed, tcod, &mut game.map);
+                game.messages.add("You close the door.", LIGHT_GREY);
+                return true;
+            }
+        }
+    }
+    game.messages.add("There is no open door to close.", LIGHT_GREY);
+    false
+}
+
+/// anything standing in lava at the end of a turn gets burned
+fn burn_in_lava(game: &mut Game, objects: &mut [DisplayObj]) {
+    for object in objects.iter_mut() {
+        if object.fighter.is_some() && game.map[object.x as usize][object.y as usize].kind.is_hazard() {
+            game.messages.add(
+                format!("The {} burns in the lava for {} hit points!", object.name, LAVA_DAMAGE),
+                ORANGE
+            );
+            object.take_damage(LAVA_DAMAGE, game);
         }
     }
 }
 
 fn is_blocked(x: i32, y: i32, map: &Map, objects: &[DisplayObj]) -> bool {
-    if map[x as usize][y as usize].blocked {
+    if map[x as usize][y as usize].blocked() {
         return true;
     }
 
@@ -1079,8 +2255,30 @@ fn is_blocked(x: i32, y: i32, map: &Map, objects: &[DisplayObj]) -> bool {
         .any(|object| object.blocks && object.get_pos() == (x,y))
 }
 
+/// up to `count` free tiles close to a position
+fn free_tiles_around(x: i32, y: i32, radius: i32, count: u32, map: &Map, objects: &[DisplayObj]) -> Vec<(i32, i32)> {
+    let mut tiles = vec![];
+    for dx in -radius..radius + 1 {
+        for dy in -radius..radius + 1 {
+            let (tx, ty) = (x + dx, y + dy);
+            if tiles.len() as u32 >= count || (dx, dy) == (0, 0) || tx < 0 || ty < 0
+                    || tx >= map.len() as i32 || ty >= map[0].len() as i32 {
+                continue;
+            }
+            if !is_blocked(tx, ty, map, objects) && !map[tx as usize][ty as usize].kind.is_hazard() {
+                tiles.push((tx, ty));
+            }
+        }
+    }
+    tiles
+}
+
 pub fn pick_item_up(object_id: usize, game: &mut Game, objects: &mut Vec<DisplayObj>){
-    if game.inventory.len() >= MAX_INV_SPACE {
+    if objects[object_id].item == Some(Item::Gold) {
+        let gold = objects.swap_remove(object_id);
+        game.gold += gold.value;
+        game.messages.add(format!("You pick up {} gold.", gold.value), GOLD);
+    } else if game.inventory.len() >= MAX_INV_SPACE {
         game.messages.add(format!("Inventory full, cannot pick up {}.", objects[object_id].name), RED);
     } else {
         let item = objects.swap_remove(object_id);
@@ -1100,10 +2298,10 @@ pub fn pick_item_up(object_id: usize, game: &mut Game, objects: &mut Vec<Display
 }
 
 pub fn drop_item(inventory_id: usize, game: &mut Game, objects: &mut Vec<DisplayObj>) {
-    let mut item = game.inventory.remove(inventory_id);
-    if item.equipment.is_some() {
-        item.dequip(&mut game.messages);
+    if game.inventory[inventory_id].equipment.is_some() && !game.inventory[inventory_id].dequip(&mut game.messages) {
+        return;
     }
+    let mut item = game.inventory.remove(inventory_id);
 
     item.set_pos(objects[PLAYER_ID].x, objects[PLAYER_ID].y);
     game.messages
@@ -1131,7 +2329,7 @@ impl Messages {
     }
 }
 
-pub fn use_item(inventory_id: usize, tcod: &mut Tcod, game: &mut Game, objects: &mut [DisplayObj]) {
+pub fn use_item(inventory_id: usize, tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<DisplayObj>) {
     use Item::*;
     // just call the "use_function" if it is defined
     if let Some(item) = game.inventory[inventory_id].item {
@@ -1143,6 +2341,15 @@ pub fn use_item(inventory_id: usize, tcod: &mut Tcod, game: &mut Game, objects:
             Sword => toggle_equipment,
             Shield => toggle_equipment,
             Helmet => toggle_equipment,
+            Key => use_key,
+            Repair => cast_repair,
+            RemoveCurse => cast_remove_curse,
+            Food => eat,
+            Torch => toggle_equipment,
+            Lantern => toggle_equipment,
+            Oil => refill_lantern,
+            Gold => return, // gold goes straight into the purse, never the inventory
+            Artifact => admire_artifact,
         };
         match on_use(inventory_id, tcod, game, objects) {
             UseResult::UsedUp => {
@@ -1162,30 +2369,379 @@ pub fn use_item(inventory_id: usize, tcod: &mut Tcod, game: &mut Game, objects:
     }
 }
 
+/// Wear down the player's equipped items that pass the filter, each with a
+/// chance to lose a point of durability. Anything worn through breaks.
+fn wear_equipment<F: Fn(&Equipment) -> bool>(game: &mut Game, worn: F) {
+    let mut rng = random::rng();
+    for inventory_id in (0..game.inventory.len()).rev() {
+        let equipment = match game.inventory[inventory_id].equipment.as_mut() {
+            Some(equipment) if equipment.equipped && equipment.max_durability > 0 && worn(equipment) => equipment,
+            _ => continue
+        };
+        if rng.gen_range(0..100) >= WEAR_CHANCE {
+            continue;
+        }
+
+        equipment.durability = equipment.durability.saturating_sub(1);
+        if equipment.durability == 0 {
+            let item = game.inventory.remove(inventory_id);
+            game.messages.add(format!("Your {} breaks!", item.name), RED);
+        } else if equipment.condition() == Some(DEGRADE_CONDITION) {
+            let name = &game.inventory[inventory_id].name;
+            game.messages.add(format!("Your {} is getting badly worn.", name), YELLOW);
+        }
+    }
+}
+
+fn cast_repair(
+    _inventory_id: usize,
+    tcod: &mut Tcod,
+    game: &mut Game,
+    _objects: &mut Vec<DisplayObj>,
+) -> UseResult {
+    // ask the player for an item to mend
+    let item_id = inventory_menu(
+        &game.inventory,
+        "Choose an item to repair, or any other key to cancel.\n",
+        &mut tcod.root,
+        game
+    );
+    let item_id = match item_id {
+        Some(item_id) => item_id,
+        None => return UseResult::Cancelled
+    };
+
+    let item = &mut game.inventory[item_id];
+    match item.equipment.as_mut() {
+        Some(equipment) if equipment.durability < equipment.max_durability => {
+            equipment.durability = equipment.max_durability;
+            game.messages.add(format!("Your {} looks as good as new!", item.name), LIGHT_BLUE);
+            UseResult::UsedUp
+        }
+        _ => {
+            game.messages.add(format!("The {} doesn't need repairing.", item.name), LIGHT_GREY);
+            UseResult::Cancelled
+        }
+    }
+}
+
+fn cast_remove_curse(
+    _inventory_id: usize,
+    _tcod: &mut Tcod,
+    game: &mut Game,
+    _objects: &mut Vec<DisplayObj>,
+) -> UseResult {
+    let mut lifted = false;
+    for item in game.inventory.iter_mut() {
+        if item.curse.take().is_some() {
+            item.identified = true;
+            lifted = true;
+        }
+    }
+
+    if lifted {
+        game.messages.add("You feel as if someone is watching over you.", LIGHT_BLUE);
+    } else {
+        game.messages.add("You feel a warmth pass over you, but nothing happens.", LIGHT_GREY);
+    }
+    UseResult::UsedUp
+}
+
+/// Eat a ration, a corpse or anything else with some nutrition in it.
+fn eat(
+    inventory_id: usize,
+    _tcod: &mut Tcod,
+    game: &mut Game,
+    _objects: &mut Vec<DisplayObj>,
+) -> UseResult {
+    let food = &game.inventory[inventory_id];
+    if game.satiety >= MAX_SATIETY {
+        game.messages.add("You couldn't eat another bite.", LIGHT_GREY);
+        return UseResult::Cancelled;
+    }
+
+    game.messages.add(format!("You eat the {}.", food.name), LIGHT_GREEN);
+    game.satiety = (game.satiety + food.nutrition).min(MAX_SATIETY);
+    UseResult::UsedUp
+}
+
 fn toggle_equipment(
     inventory_id: usize,
     _tcod: &mut Tcod,
     game: &mut Game,
-    _objects: &mut [DisplayObj],
+    _objects: &mut Vec<DisplayObj>,
 ) -> UseResult {
     let equipment = match game.inventory[inventory_id].equipment {
         Some(equipment) => equipment,
         None => return UseResult::Cancelled,
     };
 
-    // if the slot is already being used, dequip whatever is there first
+    // if the slot is already being used, dequip whatever is there first,
+    // unless a curse keeps it on
     if let Some(current) = get_equipped_in_slot(equipment.slot, &game.inventory) {
-        game.inventory[current].dequip(&mut game.messages);
+        if !game.inventory[current].dequip(&mut game.messages) {
+            return UseResult::Cancelled;
+        }
     }
     
-    if equipment.equipped {
-        game.inventory[inventory_id].dequip(&mut game.messages);
-    } else {
+    if !equipment.equipped {
         game.inventory[inventory_id].equip(&mut game.messages);
     }
     UseResult::UsedAndKept
 }
 
+fn admire_artifact(
+    _inventory_id: usize,
+    _tcod: &mut Tcod,
+    game: &mut Game,
+    _objects: &mut Vec<DisplayObj>,
+) -> UseResult {
+    game.messages.add("The crown hums with an ancient power. Bring it back to the surface!", LIGHT_VIOLET);
+    UseResult::UsedAndKept
+}
+
+fn use_key(
+    _inventory_id: usize,
+    tcod: &mut Tcod,
+    game: &mut Game,
+    objects: &mut Vec<DisplayObj>,
+) -> UseResult {
+    // unlock the first locked door next to the player
+    let (player_x, player_y) = objects[PLAYER_ID].get_pos();
+    for dx in -1..2 {
+        for dy in -1..2 {
+            let (x, y) = (player_x + dx, player_y + dy);
+            if game.map[x as usize][y as usize].kind == TileKind::DoorLocked {
+                set_tile(x, y, TileKind::DoorOpen, tcod, &mut game.map);
+                game.messages.add("The key turns, and the door swings open.", LIGHT_GREY);
+                return UseResult::UsedUp;
+            }
+        }
+    }
+    game.messages.add("There is no locked door nearby.", LIGHT_GREY);
+    UseResult::Cancelled
+}
+
+fn refill_lantern(
+    _inventory_id: usize,
+    _tcod: &mut Tcod,
+    game: &mut Game,
+    _objects: &mut Vec<DisplayObj>,
+) -> UseResult {
+    // only the lantern being carried can be filled
+    let lantern_id = match get_equipped_in_slot(Slot::Light, &game.inventory) {
+        Some(light_id) if game.inventory[light_id].item == Some(Item::Lantern) => light_id,
+        _ => {
+            game.messages.add("You have no lantern lit to fill.", LIGHT_GREY);
+            return UseResult::Cancelled;
+        }
+    };
+
+    let equipment = game.inventory[lantern_id].equipment.as_mut().unwrap();
+    let fuel = equipment.fuel.unwrap_or(0);
+    if fuel >= LANTERN_MAX_FUEL {
+        game.messages.add("Your lantern is already full.", LIGHT_GREY);
+        return UseResult::Cancelled;
+    }
+    equipment.fuel = Some((fuel + OIL_FUEL).min(LANTERN_MAX_FUEL));
+    game.messages.add("You refill your lantern, and it burns brightly again.", LIGHT_YELLOW);
+    UseResult::UsedUp
+}
+
+/// Slowly heal the player over time, faster the tougher and more experienced
+/// they are. Nobody heals on an empty stomach.
+fn regenerate(game: &mut Game, objects: &mut [DisplayObj]) {
+    let player = &objects[PLAYER_ID];
+    let max_hp = player.max_hp(game);
+    let hurt = player.fighter.map_or(false, |f| f.hp < max_hp);
+    if !player.alive || !hurt || !Hunger::from_satiety(game.satiety).can_heal() {
+        game.regeneration = 0;
+        return;
+    }
+
+    game.regeneration += max_hp as u32 * (REGEN_BASE + player.level as u32);
+    if game.regeneration >= REGEN_POINTS {
+        let amount = game.regeneration / REGEN_POINTS;
+        game.regeneration %= REGEN_POINTS;
+        objects[PLAYER_ID].heal(amount as i32, game);
+    }
+}
+
+/// whether the player can see a monster, in their line of sight and not
+/// hidden in the dark
+fn monster_in_view(tcod: &Tcod, objects: &[DisplayObj]) -> bool {
+    objects
+        .iter()
+        .any(|object| object.alive && object.ai.is_some() && object.fighter.is_some()
+            && tcod.can_see(object.x, object.y))
+}
+
+/// how many items the player can see right now
+fn items_in_view(tcod: &Tcod, objects: &[DisplayObj]) -> usize {
+    objects
+        .iter()
+        .filter(|object| object.item.is_some() && tcod.can_see(object.x, object.y))
+        .count()
+}
+
+fn start_resting(tcod: &mut Tcod, game: &mut Game, objects: &[DisplayObj]) -> PlayerAction {
+    let player = &objects[PLAYER_ID];
+    if player.fighter.map_or(true, |f| f.hp >= player.max_hp(game)) {
+        game.messages.add("You are already at full health.", LIGHT_GREY);
+        return PlayerAction::DidntTakeTurn;
+    }
+    game.messages.add("You settle down to rest.", LIGHT_GREY);
+    start_activity(Activity::Rest, tcod, game, objects)
+}
+
+/// Head for the stairs in the given direction, if the player has found them.
+fn travel_to_stairs(direction: Stairs, tcod: &mut Tcod, game: &mut Game, objects: &[DisplayObj]) -> PlayerAction {
+    let stairs = objects.iter().find(|object| {
+        object.stairs == Some(direction) && game.map[object.x as usize][object.y as usize].explored
+    });
+    match stairs {
+        Some(stairs) => {
+            let activity = Activity::Travel { x: stairs.x, y: stairs.y };
+            start_activity(activity, tcod, game, objects)
+        }
+        None => {
+            game.messages.add("You haven't found those stairs yet.", LIGHT_GREY);
+            PlayerAction::DidntTakeTurn
+        }
+    }
+}
+
+/// Set the player off on an activity, which goes on in the following turns.
+/// Nobody lets their guard down with a monster in sight.
+fn start_activity(activity: Activity, tcod: &mut Tcod, game: &mut Game, objects: &[DisplayObj]) -> PlayerAction {
+    if monster_in_view(tcod, objects) {
+        game.messages.add("Not with enemies nearby!", RED);
+    } else {
+        tcod.activity = Some(activity);
+    }
+    PlayerAction::DidntTakeTurn
+}
+
+/// Take another turn of whatever the player is busy with, stopping as soon as
+/// they press a key or something needs their attention.
+fn continue_activity(activity: Activity, tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<DisplayObj>) -> PlayerAction {
+    use tcod::input::KeyCode::NoKey;
+
+    let interruption = if tcod.key.code != NoKey {
+        Some(("You stop what you're doing.", LIGHT_GREY))
+    } else if monster_in_view(tcod, objects) {
+        Some(("You spot an enemy!", RED))
+    } else {
+        None
+    };
+    if let Some((message, color)) = interruption {
+        game.messages.add(message, color);
+        tcod.activity = None;
+        return PlayerAction::DidntTakeTurn;
+    }
+
+    match activity {
+        Activity::Rest => {
+            let player = &objects[PLAYER_ID];
+            if player.fighter.map_or(true, |f| f.hp >= player.max_hp(game)) {
+                game.messages.add("You feel rested.", LIGHT_GREEN);
+                tcod.activity = None;
+                PlayerAction::DidntTakeTurn
+            } else if !Hunger::from_satiety(game.satiety).can_heal() {
+                game.messages.add("You're too hungry to rest.", ORANGE);
+                tcod.activity = None;
+                PlayerAction::DidntTakeTurn
+            } else {
+                PlayerAction::TookTurn
+            }
+        }
+        Activity::Explore { items_seen } => {
+            let items = items_in_view(tcod, objects);
+            if items > items_seen {
+                game.messages.add("You spot something and stop exploring.", LIGHT_YELLOW);
+                tcod.activity = None;
+                return PlayerAction::DidntTakeTurn;
+            }
+            tcod.activity = Some(Activity::Explore { items_seen: items });
+
+            match travel::step_towards(game, objects, |x, y| travel::is_frontier(x, y, game)) {
+                Some((dx, dy)) => take_step(dx, dy, tcod, game, objects),
+                None => {
+                    game.messages.add("There's nothing left to explore here.", LIGHT_GREY);
+                    tcod.activity = None;
+                    PlayerAction::DidntTakeTurn
+                }
+            }
+        }
+        Activity::Travel { x, y } => {
+            if objects[PLAYER_ID].get_pos() == (x, y) {
+                tcod.activity = None;
+                return PlayerAction::DidntTakeTurn;
+            }
+            match travel::step_towards(game, objects, |tx, ty| (tx, ty) == (x, y)) {
+                Some((dx, dy)) => take_step(dx, dy, tcod, game, objects),
+                None => {
+                    game.messages.add("You can't find a way there.", LIGHT_GREY);
+                    tcod.activity = None;
+                    PlayerAction::DidntTakeTurn
+                }
+            }
+        }
+    }
+}
+
+/// Take the next step of a planned route. Opening a door on the way is
+/// progress, but if the player bumped into something instead, they stop.
+fn take_step(dx: i32, dy: i32, tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<DisplayObj>) -> PlayerAction {
+    let (x, y) = objects[PLAYER_ID].get_pos();
+    let opens_door = game.map[(x + dx) as usize][(y + dy) as usize].kind == TileKind::DoorClosed;
+    player_move_or_attack(dx, dy, tcod, game, objects);
+
+    if !opens_door && objects[PLAYER_ID].get_pos() == (x, y) {
+        game.messages.add("Something is in the way.", LIGHT_GREY);
+        tcod.activity = None;
+    }
+    PlayerAction::TookTurn
+}
+
+/// Burn a turn's worth of fuel from the equipped light. A spent torch
+/// crumbles away, a lantern just goes dark until it's refilled.
+fn burn_fuel(game: &mut Game) {
+    let light_id = match get_equipped_in_slot(Slot::Light, &game.inventory) {
+        Some(light_id) => light_id,
+        None => return
+    };
+    let fuel = match game.inventory[light_id].equipment.as_mut().and_then(|e| e.fuel.as_mut()) {
+        Some(fuel) if *fuel > 0 => fuel,
+        _ => return
+    };
+    *fuel -= 1;
+    let fuel = *fuel;
+
+    let light = &game.inventory[light_id];
+    if fuel == LOW_FUEL_WARNING {
+        game.messages.add(format!("Your {} flickers, it's running low.", light.name), YELLOW);
+    } else if fuel == 0 && light.item == Some(Item::Torch) {
+        game.messages.add(format!("Your {} burns out and crumbles to ash.", light.name), ORANGE);
+        game.inventory.remove(light_id);
+    } else if fuel == 0 {
+        game.messages.add(format!("Your {} sputters and goes out.", light.name), ORANGE);
+    }
+}
+
+/// The player's light comes from their equipment. Returns true if it changed.
+fn update_player_light(game: &Game, objects: &mut [DisplayObj]) -> bool {
+    let light = Some(Light {
+        radius: objects[PLAYER_ID].light_radius(game),
+        color: PLAYER_LIGHT_COLOR,
+        intensity: 1.0,
+        duration: None
+    });
+    let changed = objects[PLAYER_ID].light != light;
+    objects[PLAYER_ID].light = light;
+    changed
+}
+
 fn get_equipped_in_slot(slot: Slot, inventory: &[DisplayObj]) -> Option<usize> {
     for (inventory_id, item) in inventory.iter().enumerate() {
         if item
@@ -1203,7 +2759,7 @@ fn cast_heal(
     _inventory_id: usize,
     _tcod: &mut Tcod,
     game: &mut Game,
-    objects: &mut [DisplayObj],
+    objects: &mut Vec<DisplayObj>,
 ) -> UseResult {
     // heal the player
     let player = &mut objects[PLAYER_ID];
@@ -1224,7 +2780,7 @@ fn cast_lightning(
     _inventory_id: usize,
     tcod: &mut Tcod,
     game: &mut Game,
-    objects: &mut [DisplayObj],
+    objects: &mut Vec<DisplayObj>,
 ) -> UseResult {
     // find closest enemy (inside a maximum range and damage it)
     let monster_id = closest_monster(tcod, objects, LIGHTNING_RANGE);
@@ -1254,7 +2810,7 @@ fn cast_confuse(
     _inventory_id: usize,
     tcod: &mut Tcod,
     game: &mut Game,
-    objects: &mut [DisplayObj],
+    objects: &mut Vec<DisplayObj>,
 ) -> UseResult {
     // ask the player for a target to confuse
     game.messages.add(
@@ -1291,7 +2847,7 @@ fn cast_fireball(
     _inventory_id: usize,
     tcod: &mut Tcod,
     game: &mut Game,
-    objects: &mut [DisplayObj]
+    objects: &mut Vec<DisplayObj>
 ) -> UseResult {
     game.messages.add(
         "Left-click a target tile to strike, right-click to cancel.",
@@ -1308,6 +2864,7 @@ fn cast_fireball(
             FIREBALL_RADIUS
         ), ORANGE
     );
+    objects.push(lighting::flash(x, y, FIREBALL_RADIUS * 2, ORANGE, FIREBALL_FLASH_TURNS));
 
     let mut xp_to_gain: u32 = 0;
 
@@ -1344,7 +2901,7 @@ fn closest_monster(tcod: &Tcod, objects: &[DisplayObj], max_range: i32) -> Optio
         if (id != PLAYER_ID)
             && object.fighter.is_some()
             && object.ai.is_some()
-            && tcod.fov.is_in_fov(object.x, object.y)
+            && tcod.can_see(object.x, object.y)
         {
             // calculate distance between this object and the player
             let dist = objects[PLAYER_ID].distance_to(object);
@@ -1377,15 +2934,22 @@ fn target_tile(
 
         render_all(tcod, game, objects, false);
         
-        let (x, y) = (tcod.mouse.cx as i32, tcod.mouse.cy as i32);
+        let (x, y) = match tcod.camera.to_world(tcod.mouse.cx as i32, tcod.mouse.cy as i32) {
+            Some(pos) => pos,
+            None => (-1, -1)
+        };
 
-        let in_fov = (x < game.game_settings.map_w) && (y < game.game_settings.map_h) && 
-                tcod.fov.is_in_fov(x, y);
+        let in_fov = (x >= 0) && (y >= 0) &&
+                (x < game.game_settings.map_w) && (y < game.game_settings.map_h) && 
+                tcod.can_see(x, y);
         let in_range = max_range.map_or(true, |range| objects[PLAYER_ID].distance(x, y) <= range);
+        // the click is used up here, so the game loop doesn't take it as travel
         if tcod.mouse.lbutton_pressed && in_fov && in_range {
+            tcod.mouse.lbutton_pressed = false;
             return Some((x, y));
         }
         if tcod.mouse.rbutton_pressed || tcod.key.code == Escape {
+            tcod.mouse.rbutton_pressed = false;
             return None;
         }
     }
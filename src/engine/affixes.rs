@@ -0,0 +1,161 @@
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tcod::colors::*;
+
+use super::random;
+use super::conf::{Affix, AffixKind, TransitionTables};
+use super::{from_dungeon_level, DisplayObj, Slot};
+
+/// How special a piece of equipment is. The rarer it is, the more and the
+/// stronger the affixes it rolls.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Rarity {
+    Common,
+    Magic,
+    Rare,
+    Legendary
+}
+
+impl Default for Rarity {
+    fn default() -> Self {
+        Rarity::Common
+    }
+}
+
+impl Rarity {
+    /// color of the item's name in menus
+    pub fn color(&self) -> Color {
+        match self {
+            Rarity::Common => WHITE,
+            Rarity::Magic => LIGHT_BLUE,
+            Rarity::Rare => YELLOW,
+            Rarity::Legendary => ORANGE
+        }
+    }
+
+    /// multiplier for the bonuses and effects of each affix
+    fn power(&self) -> i32 {
+        match self {
+            Rarity::Legendary => 2,
+            _ => 1
+        }
+    }
+
+    /// multiplier for the item's price
+    fn price_factor(&self) -> u32 {
+        match self {
+            Rarity::Common => 1,
+            Rarity::Magic => 2,
+            Rarity::Rare => 4,
+            Rarity::Legendary => 8
+        }
+    }
+}
+
+/// Something extra an enchanted weapon does on every hit.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Effect {
+    /// extra fire damage
+    Burning { damage: i32 },
+    /// heals the wielder
+    Drain { amount: i32 }
+}
+
+impl Effect {
+    fn scaled(&self, power: i32) -> Effect {
+        match *self {
+            Effect::Burning { damage } => Effect::Burning { damage: damage * power },
+            Effect::Drain { amount } => Effect::Drain { amount: amount * power }
+        }
+    }
+}
+
+/// Roll a rarity for a freshly made piece of equipment and give it affixes
+/// to match: a magic item gets a prefix or a suffix, rare and legendary ones both.
+pub fn enchant(item: &mut DisplayObj, tables: &TransitionTables, level: u32) {
+    let slot = match item.equipment {
+        Some(equipment) => equipment.slot,
+        None => return
+    };
+    let mut rng = random::rng();
+    if rng.gen_range(0..100) < from_dungeon_level(&tables.curse_chance, level) {
+        curse(item, slot, tables, level);
+    }
+    let rarity = roll_rarity(tables, level);
+
+    let (prefix, suffix) = match rarity {
+        Rarity::Common => (false, false),
+        Rarity::Magic => {
+            let prefix: bool = rng.gen();
+            (prefix, !prefix)
+        }
+        Rarity::Rare | Rarity::Legendary => (true, true)
+    };
+
+    let prefix = if prefix { roll_affix(AffixKind::Prefix, slot, tables, level) } else { None };
+    let suffix = if suffix { roll_affix(AffixKind::Suffix, slot, tables, level) } else { None };
+    // nothing fits this kind of equipment, so it stays plain
+    if prefix.is_none() && suffix.is_none() {
+        return;
+    }
+
+    if let Some(affix) = prefix {
+        apply(affix, rarity, item);
+        item.name = format!("{} {}", affix.name, item.name);
+    }
+    if let Some(affix) = suffix {
+        apply(affix, rarity, item);
+        item.name = format!("{} {}", item.name, affix.name);
+    }
+    item.rarity = rarity;
+    item.value *= rarity.price_factor();
+}
+
+/// Lay a curse on the item. It keeps its name, so the curse is a surprise
+/// until the item is identified.
+fn curse(item: &mut DisplayObj, slot: Slot, tables: &TransitionTables, level: u32) {
+    if let Some(affix) = roll_affix(AffixKind::Curse, slot, tables, level) {
+        apply(affix, Rarity::Common, item);
+        item.curse = Some(affix.name.clone());
+    }
+}
+
+fn roll_rarity(tables: &TransitionTables, level: u32) -> Rarity {
+    let weights: Vec<u32> = tables.rarities
+        .iter()
+        .map(|rarity| from_dungeon_level(&rarity.weight, level))
+        .collect();
+    match WeightedIndex::new(weights) {
+        Ok(choices) => tables.rarities[choices.sample(&mut random::rng())].rarity,
+        Err(_) => Rarity::Common
+    }
+}
+
+fn roll_affix(kind: AffixKind, slot: Slot, tables: &TransitionTables, level: u32) -> Option<&Affix> {
+    let weights: Vec<u32> = tables.affixes
+        .iter()
+        .map(|affix| {
+            if affix.kind == kind && (affix.slots.is_empty() || affix.slots.contains(&slot)) {
+                from_dungeon_level(&affix.weight, level)
+            } else {
+                0
+            }
+        })
+        .collect();
+    let choices = WeightedIndex::new(weights).ok()?;
+    Some(&tables.affixes[choices.sample(&mut random::rng())])
+}
+
+fn apply(affix: &Affix, rarity: Rarity, item: &mut DisplayObj) {
+    let power = rarity.power();
+    if let Some(equipment) = item.equipment.as_mut() {
+        equipment.max_hp_bonus += affix.max_hp_bonus * power;
+        equipment.power_bonus += affix.power_bonus * power;
+        equipment.defense_bonus += affix.defense_bonus * power;
+        equipment.light_radius_bonus += affix.light_radius_bonus * power;
+    }
+    if let Some(effect) = affix.effect {
+        item.effects.push(effect.scaled(power));
+    }
+}
@@ -1,33 +1,168 @@
-use std::{error::Error, fs::File, io::Read};
+use std::error::Error;
 
-use serde::{Deserialize, Serialize};
+use rand::Rng;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tcod::Color;
 
-use super::{Ai, DisplayObj, Equipment, Fighter, Item};
+use super::random;
+use super::{check_generators, Ai, DisplayObj, Equipment, Fighter, GameSettings, Item, Slot, TileKind};
+use super::affixes::{Effect, Rarity};
+use super::lighting::Light;
+use super::mapgen::MapGenerator;
+use super::mods::{self, DataPack};
+use super::traps::Trap;
+use super::validation::{self, Report};
 
-const SETTINGS_FILE: &str = "settings.json";
+pub const SETTINGS_FILE: &str = "settings.json";
 
-#[derive(Debug, Serialize, Deserialize)]
+/// What can be set on the command line, over the settings file.
+#[derive(Debug)]
+pub struct Options {
+    /// the settings file to load instead of settings.json
+    pub settings_file: String,
+    pub seed: Option<u64>,
+    pub font: Option<String>,
+    /// only check the settings and keymap files, without starting the game
+    pub check_config: bool
+}
+
+impl Options {
+    /// Read `--settings <file>`, `--seed <number>`, `--font <file>` and
+    /// `--check-config` from the program's arguments.
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut options = Options { settings_file: SETTINGS_FILE.into(), seed: None, font: None, check_config: false };
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--settings" => options.settings_file = value()?,
+                "--font" => options.font = Some(value()?),
+                "--check-config" => options.check_config = true,
+                "--seed" => {
+                    let seed = value()?;
+                    options.seed = Some(seed.parse().map_err(|_| format!("invalid seed '{}'", seed))?);
+                }
+                _ => return Err(format!("unknown option '{}'", arg))
+            }
+        }
+        Ok(options)
+    }
+
+    /// override the game settings with whatever was given on the command line
+    pub fn apply(&self, game_settings: &mut GameSettings) {
+        if self.seed.is_some() {
+            game_settings.seed = self.seed;
+        }
+        if let Some(font) = self.font.as_ref() {
+            game_settings.font = font.clone();
+        }
+    }
+}
+
+/// The settings file, as far as the game settings go.
+#[derive(Deserialize)]
+struct SettingsFile {
+    #[serde(default)]
+    game: GameSettings
+}
+
+pub fn load_game_settings(packs: &[DataPack]) -> Result<GameSettings, Box<dyn Error>> {
+    Ok(parse_packs::<SettingsFile>(packs)?.game)
+}
+
+/// Read the packs as one settings file. The base pack is read on its own
+/// first, so mistakes in it come with a line number.
+fn parse_packs<T: DeserializeOwned>(packs: &[DataPack]) -> Result<T, Box<dyn Error>> {
+    let base = packs.first().ok_or("no settings file loaded")?;
+    let settings = serde_json::from_str::<T>(&base.json)
+            .map_err(|e| format!("{}: {}", base.path, e))?;
+    if packs.len() == 1 {
+        return Ok(settings);
+    }
+
+    let names: Vec<&str> = packs[1..].iter().map(|pack| pack.name.as_str()).collect();
+    Ok(serde_json::from_value::<T>(mods::merge(packs)?)
+            .map_err(|e| format!("{} with mods {}: {}", base.path, names.join(", "), e))?)
+}
+
+/// Check everything in the settings file and the mods laid over it, reporting
+/// every problem rather than stopping at the first.
+pub fn check_data_packs(packs: &[DataPack]) -> Report {
+    let mut report = Report::default();
+    let game_settings = match load_game_settings(packs) {
+        Ok(game_settings) => {
+            if let Err(e) = game_settings.validate() {
+                report.errors.extend(e.lines().map(|line| format!("game: {}", line)));
+            }
+            Some(game_settings)
+        }
+        Err(e) => {
+            report.errors.push(e.to_string());
+            None
+        }
+    };
+    match read_tables(packs) {
+        Ok((tables, tables_report)) => {
+            report.errors.extend(tables_report.errors);
+            report.warnings.extend(tables_report.warnings);
+            if let Some(Err(e)) = game_settings.map(|game_settings| check_generators(&tables, &game_settings)) {
+                report.errors.extend(e.lines().map(|line| format!("game: {}", line)));
+            }
+        }
+        Err(e) => report.errors.push(e.to_string())
+    }
+    report
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Transition {
     pub level: u32,
     pub value: u32,
 }
 
+/// Picks the map generator used from a dungeon level onwards.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GeneratorTransition {
+    pub level: u32,
+    pub generator: MapGenerator,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ObjectConfiguration {
     name: String,
     char: char,
     color: Color,
+    #[serde(default)]
     pub transition_table: Vec<Transition>,
     pub fighter: Option<Fighter>,
     pub ai: Option<Ai>,
     pub item: Option<Item>,
-    pub equipment: Option<Equipment>
+    pub equipment: Option<Equipment>,
+    pub trap: Option<Trap>,
+    pub light: Option<Light>,
+    /// spawn as a group rather than alone
+    #[serde(default)]
+    pub pack: Option<Pack>,
+    /// what it drops when killed, or holds when it's a container
+    #[serde(default)]
+    pub drops: Option<LootDrop>,
+    #[serde(default)]
+    pub container: bool,
+    /// what shops charge for an item, 0 for things that can't be bought
+    #[serde(default)]
+    pub price: u32,
+    /// most gold carried by a monster or found in a gold pile
+    #[serde(default)]
+    pub gold: u32,
+    /// how much eating it fills the player up
+    #[serde(default)]
+    pub nutrition: u32
 }
 
 impl ObjectConfiguration {
     pub fn new(name: String, char: char, color: Color, tables: Vec<Transition>, 
-            fighter: Option<Fighter>, ai: Option<Ai>, item: Option<Item>, equipment: Option<Equipment>) -> Self {
+            fighter: Option<Fighter>, ai: Option<Ai>, item: Option<Item>, equipment: Option<Equipment>,
+            trap: Option<Trap>, light: Option<Light>, pack: Option<Pack>,
+            drops: Option<LootDrop>, container: bool, price: u32, gold: u32, nutrition: u32) -> Self {
         ObjectConfiguration {
             name: name,
             char: char,
@@ -36,12 +171,32 @@ impl ObjectConfiguration {
             fighter: fighter,
             ai: ai,
             item: item,
-            equipment: equipment
+            equipment: equipment,
+            trap: trap,
+            light: light,
+            pack: pack,
+            drops: drops,
+            container: container,
+            price: price,
+            gold: gold,
+            nutrition: nutrition
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn as_object(&self, x: i32, y: i32) -> DisplayObj {
         let mut object = DisplayObj::new(x, y, self.char, &self.name,  self.color, true);
+        object.light = self.light;
+        object.drops = self.drops.clone();
+        object.nutrition = self.nutrition;
+        object.value = if self.gold > 0 {
+            random::rng().gen_range(1..self.gold + 1)
+        } else {
+            self.price
+        };
 
         if self.fighter != None {
             object.fighter = self.fighter;
@@ -49,20 +204,197 @@ impl ObjectConfiguration {
             object.alive = true;
         } else if self.item != None {
             object.item = self.item;
-            object.equipment = self.equipment;
+            // freshly found equipment is in perfect condition
+            object.equipment = self.equipment.map(|mut equipment| {
+                equipment.durability = equipment.max_durability;
+                equipment
+            });
             object.always_visible = true;
             object.blocks = false;
+        } else if self.trap != None {
+            // traps stay hidden until found
+            object.trap = self.trap;
+            object.hidden = true;
+            object.blocks = false;
+        } else {
+            // plain dungeon features, like braziers, only containers are in the way
+            object.always_visible = true;
+            object.blocks = self.container;
+            object.container = self.container;
         }
         object
     }
 }
 
+/// Rolls made on a loot table when a monster dies or a container is opened.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LootDrop {
+    pub table: String,
+    pub rolls: u32,
+    /// percent chance of each roll dropping something, by depth
+    pub chance: Vec<Transition>
+}
+
+/// One entry in a loot table: an item from the spawn tables, or another
+/// loot table to roll on instead.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LootEntry {
+    #[serde(default)]
+    pub item: Option<String>,
+    #[serde(default)]
+    pub table: Option<String>,
+    /// how likely this entry is, by depth
+    pub weight: Vec<Transition>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LootTable {
+    pub name: String,
+    pub entries: Vec<LootEntry>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AffixKind {
+    Prefix,
+    Suffix,
+    /// hidden until the item is identified, and keeps it from being taken off
+    Curse
+}
+
+/// A prefix or suffix that enchanted equipment can roll, adding to its
+/// bonuses and maybe an on-hit effect, or a curse weighing it down.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Affix {
+    pub name: String,
+    pub kind: AffixKind,
+    /// slots of the equipment it can appear on, any slot when empty
+    #[serde(default)]
+    pub slots: Vec<Slot>,
+    #[serde(default)]
+    pub max_hp_bonus: i32,
+    #[serde(default)]
+    pub power_bonus: i32,
+    #[serde(default)]
+    pub defense_bonus: i32,
+    #[serde(default)]
+    pub light_radius_bonus: i32,
+    #[serde(default)]
+    pub effect: Option<Effect>,
+    pub weight: Vec<Transition>
+}
+
+/// How likely equipment is to roll a rarity, by depth.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RarityWeight {
+    pub rarity: Rarity,
+    pub weight: Vec<Transition>
+}
+
+/// Makes a monster spawn as a group. Followers are looked up by name in the
+/// same table; without them the group is more of the same monster.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Pack {
+    /// size of the whole group, the first monster included
+    pub min_size: u32,
+    pub max_size: u32,
+    /// monsters that follow the first one around as their leader
+    #[serde(default)]
+    pub followers: Option<String>
+}
+
+/// Monsters of one kind that spawn alongside a unique.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MinionGroup {
+    pub monster: String,
+    pub count: u32
+}
+
+/// A named monster that turns up at most once per run, somewhere between two
+/// depths. Its loot and minions are looked up by name in the spawn tables.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UniqueConfiguration {
+    #[serde(flatten)]
+    pub monster: ObjectConfiguration,
+    pub min_level: u32,
+    pub max_level: u32,
+    /// percent chance of appearing on each eligible level
+    pub chance: u32,
+    #[serde(default)]
+    pub loot: Vec<String>,
+    #[serde(default)]
+    pub minions: Vec<MinionGroup>,
+    #[serde(default)]
+    pub death_message: Option<String>
+}
+
+/// What a character in a vault template stands for. Monsters and items are
+/// looked up by name in the spawn tables and stand on floor unless a tile is given.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VaultLegend {
+    pub glyph: char,
+    pub tile: Option<TileKind>,
+    pub monster: Option<String>,
+    pub item: Option<String>
+}
+
+/// A hand-authored room that may be stamped into levels between two depths.
+/// Besides the legend, `#` is wall, `.` is floor, `+` is a door leading in and
+/// a space leaves the generated map untouched.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VaultTemplate {
+    pub name: String,
+    pub min_level: u32,
+    pub max_level: u32,
+    /// percent chance of appearing on each eligible level
+    pub chance: u32,
+    pub rows: Vec<String>,
+    #[serde(default)]
+    pub legend: Vec<VaultLegend>
+}
+
+impl VaultTemplate {
+    pub fn width(&self) -> i32 {
+        self.rows.iter().map(|row| row.chars().count()).max().unwrap_or(0) as i32
+    }
+
+    pub fn height(&self) -> i32 {
+        self.rows.len() as i32
+    }
+
+    pub fn legend_for(&self, glyph: char) -> Option<&VaultLegend> {
+        self.legend.iter().find(|entry| entry.glyph == glyph)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TransitionTables {
     pub max_monsters: Vec<Transition>,
     pub max_items: Vec<Transition>,
     pub monsters: Vec<ObjectConfiguration>,
-    pub items: Vec<ObjectConfiguration>
+    pub items: Vec<ObjectConfiguration>,
+    #[serde(default)]
+    pub max_traps: Vec<Transition>,
+    #[serde(default)]
+    pub traps: Vec<ObjectConfiguration>,
+    #[serde(default)]
+    pub max_features: Vec<Transition>,
+    #[serde(default)]
+    pub features: Vec<ObjectConfiguration>,
+    #[serde(default)]
+    pub uniques: Vec<UniqueConfiguration>,
+    #[serde(default)]
+    pub loot_tables: Vec<LootTable>,
+    #[serde(default)]
+    pub rarities: Vec<RarityWeight>,
+    #[serde(default)]
+    pub affixes: Vec<Affix>,
+    /// percent chance for equipment to come out cursed, by depth
+    #[serde(default)]
+    pub curse_chance: Vec<Transition>,
+    #[serde(default)]
+    pub map_generators: Vec<GeneratorTransition>,
+    #[serde(default)]
+    pub vaults: Vec<VaultTemplate>
 }
 
 impl TransitionTables {
@@ -71,19 +403,46 @@ impl TransitionTables {
             max_monsters : max_monsters,
             max_items: max_items,
             monsters : Vec::new(),
-            items: Vec::new()
+            items: Vec::new(),
+            max_traps: Vec::new(),
+            traps: Vec::new(),
+            max_features: Vec::new(),
+            features: Vec::new(),
+            uniques: Vec::new(),
+            loot_tables: Vec::new(),
+            rarities: Vec::new(),
+            affixes: Vec::new(),
+            curse_chance: Vec::new(),
+            map_generators: Vec::new(),
+            vaults: Vec::new()
         }
     }
-}
 
-pub fn load_weighted_tables() -> Result<TransitionTables, Box<dyn Error>> {
-    let mut json_settings = String::new();
-    let mut file = File::open(SETTINGS_FILE)?;
-    file.read_to_string(&mut json_settings)?;
+    pub fn find_monster(&self, name: &str) -> Option<&ObjectConfiguration> {
+        self.monsters.iter().find(|monster| monster.name == name)
+    }
+
+    pub fn find_item(&self, name: &str) -> Option<&ObjectConfiguration> {
+        self.items.iter().find(|item| item.name == name)
+    }
+
+    pub fn find_loot_table(&self, name: &str) -> Option<&LootTable> {
+        self.loot_tables.iter().find(|table| table.name == name)
+    }
+}
 
-    let tables = serde_json
-            ::from_str
-            ::<TransitionTables>(&json_settings)?;
+/// Load the spawn tables, refusing them if they have any errors. Warnings
+/// are returned alongside.
+pub fn load_weighted_tables(packs: &[DataPack]) -> Result<(TransitionTables, Vec<String>), Box<dyn Error>> {
+    let (tables, report) = read_tables(packs)?;
+    if !report.is_ok() {
+        return Err(report.errors.join("\n").into());
+    }
+    Ok((tables, report.warnings))
+}
 
-    Ok(tables)
+fn read_tables(packs: &[DataPack]) -> Result<(TransitionTables, Report), Box<dyn Error>> {
+    let tables = parse_packs::<TransitionTables>(packs)?;
+    let report = validation::check_tables(&tables, packs);
+    Ok((tables, report))
 }
\ No newline at end of file
@@ -0,0 +1,111 @@
+use rand::Rng;
+use tcod::colors::*;
+
+use super::random;
+use super::{DisplayObj, Game, PLAYER_ID};
+
+/// How much the player can eat before they're stuffed, and how full a new character starts.
+pub const MAX_SATIETY: u32 = 1500;
+
+// satiety below which each hunger state sets in
+const HUNGRY_SATIETY: u32 = 300;
+const WEAK_SATIETY: u32 = 100;
+
+/// percent chance each turn for a starving player to lose a hit point
+const STARVE_CHANCE: u32 = 20;
+/// nutrition in a corpse, per hit point of the monster it was
+pub const CORPSE_NUTRITION: u32 = 15;
+
+/// satiety of saves from before the player could go hungry
+pub fn full_satiety() -> u32 {
+    MAX_SATIETY
+}
+
+/// How hungry the player is, from how much they've eaten.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hunger {
+    Fed,
+    Hungry,
+    Weak,
+    Starving
+}
+
+impl Hunger {
+    pub fn from_satiety(satiety: u32) -> Hunger {
+        if satiety == 0 {
+            Hunger::Starving
+        } else if satiety < WEAK_SATIETY {
+            Hunger::Weak
+        } else if satiety < HUNGRY_SATIETY {
+            Hunger::Hungry
+        } else {
+            Hunger::Fed
+        }
+    }
+
+    /// status shown in the side panel, empty when there's nothing to worry about
+    pub fn label(&self) -> &'static str {
+        match self {
+            Hunger::Fed => "",
+            Hunger::Hungry => "Hungry",
+            Hunger::Weak => "Weak",
+            Hunger::Starving => "Starving"
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            Hunger::Fed => WHITE,
+            Hunger::Hungry => YELLOW,
+            Hunger::Weak => ORANGE,
+            Hunger::Starving => RED
+        }
+    }
+
+    /// whether the player's wounds still close up on their own
+    pub fn can_heal(&self) -> bool {
+        match self {
+            Hunger::Fed | Hunger::Hungry => true,
+            Hunger::Weak | Hunger::Starving => false
+        }
+    }
+
+    /// taken off the player's attack
+    pub fn power_penalty(&self) -> i32 {
+        match self {
+            Hunger::Fed => 0,
+            Hunger::Hungry => 1,
+            Hunger::Weak | Hunger::Starving => 2
+        }
+    }
+
+    /// taken off the player's defense
+    pub fn defense_penalty(&self) -> i32 {
+        match self {
+            Hunger::Fed | Hunger::Hungry => 0,
+            Hunger::Weak | Hunger::Starving => 1
+        }
+    }
+}
+
+/// Spend a turn's worth of food, warning the player as they get hungrier.
+/// Once there's nothing left, they slowly starve.
+pub fn digest(game: &mut Game, objects: &mut [DisplayObj]) {
+    let before = Hunger::from_satiety(game.satiety);
+    game.satiety = game.satiety.saturating_sub(1);
+    let after = Hunger::from_satiety(game.satiety);
+
+    if after != before {
+        let message = match after {
+            Hunger::Hungry => "You are getting hungry.",
+            Hunger::Weak => "You feel weak with hunger.",
+            Hunger::Starving => "You are starving!",
+            Hunger::Fed => return
+        };
+        game.messages.add(message, after.color());
+    }
+
+    if after == Hunger::Starving && random::rng().gen_range(0..100) < STARVE_CHANCE {
+        objects[PLAYER_ID].take_damage(1, game);
+    }
+}
@@ -0,0 +1,346 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use tcod::input::{Key, KeyCode};
+
+const KEYMAP_FILE: &str = "keymap.json";
+
+/// Something the player can do with a single key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveNorth,
+    MoveSouth,
+    MoveWest,
+    MoveEast,
+    MoveNorthWest,
+    MoveNorthEast,
+    MoveSouthWest,
+    MoveSouthEast,
+    Wait,
+    PickUp,
+    Inventory,
+    Drop,
+    Descend,
+    Ascend,
+    Explore,
+    Rest,
+    Search,
+    Disarm,
+    CloseDoor,
+    Character,
+    /// the key reference screen
+    Help
+}
+
+impl Action {
+    /// every action, in the order the key reference lists them
+    pub const ALL: [Action; 21] = [
+        Action::MoveNorth, Action::MoveSouth, Action::MoveWest, Action::MoveEast,
+        Action::MoveNorthWest, Action::MoveNorthEast, Action::MoveSouthWest, Action::MoveSouthEast,
+        Action::Wait, Action::PickUp, Action::Inventory, Action::Drop, Action::Descend, Action::Ascend,
+        Action::Explore, Action::Rest, Action::Search, Action::Disarm, Action::CloseDoor,
+        Action::Character, Action::Help
+    ];
+
+    /// the step a movement action takes
+    pub fn direction(&self) -> Option<(i32, i32)> {
+        match self {
+            Action::MoveNorth => Some((0, -1)),
+            Action::MoveSouth => Some((0, 1)),
+            Action::MoveWest => Some((-1, 0)),
+            Action::MoveEast => Some((1, 0)),
+            Action::MoveNorthWest => Some((-1, -1)),
+            Action::MoveNorthEast => Some((1, -1)),
+            Action::MoveSouthWest => Some((-1, 1)),
+            Action::MoveSouthEast => Some((1, 1)),
+            _ => None
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Action::MoveNorth => "Move north",
+            Action::MoveSouth => "Move south",
+            Action::MoveWest => "Move west",
+            Action::MoveEast => "Move east",
+            Action::MoveNorthWest => "Move north-west",
+            Action::MoveNorthEast => "Move north-east",
+            Action::MoveSouthWest => "Move south-west",
+            Action::MoveSouthEast => "Move south-east",
+            Action::Wait => "Wait a turn",
+            Action::PickUp => "Pick up",
+            Action::Inventory => "Inventory",
+            Action::Drop => "Drop",
+            Action::Descend => "Go down stairs",
+            Action::Ascend => "Go up stairs",
+            Action::Explore => "Explore",
+            Action::Rest => "Rest until healed",
+            Action::Search => "Search",
+            Action::Disarm => "Disarm trap",
+            Action::CloseDoor => "Close door",
+            Action::Character => "Character",
+            Action::Help => "Keys"
+        }
+    }
+}
+
+/// Built-in key layouts the keymap file can start from.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Preset {
+    /// arrows, numpad and the navigation block
+    Numpad,
+    /// hjkl and yubn
+    Vi,
+    /// qwe/asd/zxc, for keyboards without a numpad
+    Wasd
+}
+
+impl Default for Preset {
+    fn default() -> Self {
+        Preset::Numpad
+    }
+}
+
+impl Preset {
+    fn bindings(&self) -> HashMap<Action, Vec<&'static str>> {
+        use Action::*;
+        let mut bindings: HashMap<Action, Vec<&'static str>> = [
+            (PickUp, vec!["g"]),
+            (Inventory, vec!["i"]),
+            (Drop, vec!["d"]),
+            (Descend, vec![">"]),
+            (Ascend, vec!["<"]),
+            (Explore, vec!["x"]),
+            (Rest, vec!["R"]),
+            (Search, vec!["s"]),
+            (Disarm, vec!["D"]),
+            (CloseDoor, vec!["C"]),
+            (Character, vec!["c"]),
+            (Help, vec!["?"])
+        ].iter().cloned().collect();
+
+        let movement = match self {
+            Preset::Numpad => [
+                (MoveNorth, vec!["Up", "NumPad8"]),
+                (MoveSouth, vec!["Down", "NumPad2"]),
+                (MoveWest, vec!["Left", "NumPad4"]),
+                (MoveEast, vec!["Right", "NumPad6"]),
+                (MoveNorthWest, vec!["Home", "NumPad7"]),
+                (MoveNorthEast, vec!["PageUp", "NumPad9"]),
+                (MoveSouthWest, vec!["End", "NumPad1"]),
+                (MoveSouthEast, vec!["PageDown", "NumPad3"]),
+                (Wait, vec!["NumPad5"])
+            ],
+            Preset::Vi => [
+                (MoveNorth, vec!["k", "Up"]),
+                (MoveSouth, vec!["j", "Down"]),
+                (MoveWest, vec!["h", "Left"]),
+                (MoveEast, vec!["l", "Right"]),
+                (MoveNorthWest, vec!["y"]),
+                (MoveNorthEast, vec!["u"]),
+                (MoveSouthWest, vec!["b"]),
+                (MoveSouthEast, vec!["n"]),
+                (Wait, vec!["."])
+            ],
+            Preset::Wasd => [
+                (MoveNorth, vec!["w", "Up"]),
+                (MoveSouth, vec!["x", "Down"]),
+                (MoveWest, vec!["a", "Left"]),
+                (MoveEast, vec!["d", "Right"]),
+                (MoveNorthWest, vec!["q"]),
+                (MoveNorthEast, vec!["e"]),
+                (MoveSouthWest, vec!["z"]),
+                (MoveSouthEast, vec!["c"]),
+                (Wait, vec!["s"])
+            ]
+        };
+        bindings.extend(movement.iter().cloned());
+
+        // the movement keys take over some letters, so those actions move elsewhere
+        if *self == Preset::Wasd {
+            bindings.insert(Drop, vec!["t"]);
+            bindings.insert(Explore, vec!["o"]);
+            bindings.insert(Search, vec!["f"]);
+            bindings.insert(Character, vec!["v"]);
+        }
+        bindings
+    }
+}
+
+/// What the keymap file holds: a preset to start from, and the keys of any
+/// actions that should be bound differently.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct KeymapConfig {
+    #[serde(default)]
+    preset: Preset,
+    #[serde(default)]
+    bindings: HashMap<Action, Vec<String>>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BoundKey {
+    /// a printable character, as typed
+    Text(char),
+    /// a key that doesn't type anything, like the arrows or the numpad
+    Code(KeyCode)
+}
+
+impl BoundKey {
+    fn parse(name: &str) -> Option<BoundKey> {
+        use KeyCode::*;
+        let mut chars = name.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Some(BoundKey::Text(c));
+        }
+        let code = match name {
+            "Up" => Up,
+            "Down" => Down,
+            "Left" => Left,
+            "Right" => Right,
+            "Home" => Home,
+            "End" => End,
+            "PageUp" => PageUp,
+            "PageDown" => PageDown,
+            "Insert" => Insert,
+            "Delete" => Delete,
+            "Enter" => Enter,
+            "Tab" => Tab,
+            "Backspace" => Backspace,
+            "Space" => Spacebar,
+            "NumPad0" => NumPad0,
+            "NumPad1" => NumPad1,
+            "NumPad2" => NumPad2,
+            "NumPad3" => NumPad3,
+            "NumPad4" => NumPad4,
+            "NumPad5" => NumPad5,
+            "NumPad6" => NumPad6,
+            "NumPad7" => NumPad7,
+            "NumPad8" => NumPad8,
+            "NumPad9" => NumPad9,
+            _ => return None
+        };
+        Some(BoundKey::Code(code))
+    }
+
+    fn matches(&self, key: &Key) -> bool {
+        match *self {
+            BoundKey::Text(c) => key.code == KeyCode::Text && key.text().chars().next() == Some(c),
+            BoundKey::Code(code) => key.code == code
+        }
+    }
+}
+
+/// Which key does what.
+#[derive(Debug)]
+pub struct Keymap {
+    bindings: Vec<(String, BoundKey, Action)>
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::from_config(KeymapConfig::default()).expect("the built-in presets are valid")
+    }
+}
+
+impl Keymap {
+    fn from_config(config: KeymapConfig) -> Result<Keymap, Box<dyn Error>> {
+        let mut names: HashMap<Action, Vec<String>> = config.preset
+            .bindings()
+            .into_iter()
+            .map(|(action, keys)| (action, keys.into_iter().map(String::from).collect()))
+            .collect();
+        names.extend(config.bindings);
+
+        let mut bindings: Vec<(String, BoundKey, Action)> = vec![];
+        for action in Action::ALL.iter() {
+            for name in names.get(action).into_iter().flatten() {
+                let key = BoundKey::parse(name)
+                    .ok_or_else(|| format!("unknown key '{}' for {:?}", name, action))?;
+                if let Some((_, _, other)) = bindings.iter().find(|(_, bound, _)| *bound == key) {
+                    return Err(format!("key '{}' is bound to both {:?} and {:?}", name, other, action).into());
+                }
+                bindings.push((name.clone(), key, *action));
+            }
+        }
+        Ok(Keymap { bindings: bindings })
+    }
+
+    /// the action a key press stands for, if any
+    pub fn action(&self, key: &Key) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(_, bound, _)| bound.matches(key))
+            .map(|&(_, _, action)| action)
+    }
+
+    /// The key reference screen: every action with the keys bound to it.
+    pub fn reference(&self) -> String {
+        let mut lines = vec!["Keys".to_string(), String::new()];
+        for action in Action::ALL.iter() {
+            let keys: Vec<&str> = self.bindings
+                .iter()
+                .filter(|(_, _, bound)| bound == action)
+                .map(|(name, _, _)| name.as_str())
+                .collect();
+            let keys = if keys.is_empty() { "-".to_string() } else { keys.join(", ") };
+            lines.push(format!("{:<20}{}", action.description(), keys));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Load the keymap file. Without one, the numpad preset is used.
+pub fn load_keymap() -> Result<Keymap, Box<dyn Error>> {
+    let mut json_keymap = String::new();
+    match File::open(KEYMAP_FILE) {
+        Ok(mut file) => file.read_to_string(&mut json_keymap)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Keymap::default()),
+        Err(e) => return Err(e.into())
+    };
+
+    let config = serde_json::from_str::<KeymapConfig>(&json_keymap)?;
+    Keymap::from_config(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(preset: Preset, bindings: &[(Action, &[&str])]) -> KeymapConfig {
+        KeymapConfig {
+            preset: preset,
+            bindings: bindings
+                .iter()
+                .map(|(action, keys)| (*action, keys.iter().map(|key| key.to_string()).collect()))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn presets_have_no_conflicts() {
+        for preset in [Preset::Numpad, Preset::Vi, Preset::Wasd].iter() {
+            assert!(Keymap::from_config(config(*preset, &[])).is_ok(), "{:?}", preset);
+        }
+    }
+
+    #[test]
+    fn binding_a_key_twice_is_a_conflict() {
+        let result = Keymap::from_config(config(Preset::Numpad, &[(Action::Search, &["g"])]));
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("PickUp") && error.contains("Search"), "{}", error);
+    }
+
+    #[test]
+    fn rebinding_an_action_frees_its_old_keys() {
+        let keymap = Keymap::from_config(config(Preset::Numpad, &[(Action::PickUp, &["p"]), (Action::Search, &["g"])]));
+        assert!(keymap.is_ok());
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let result = Keymap::from_config(config(Preset::Numpad, &[(Action::Rest, &["F13"])]));
+        assert!(result.unwrap_err().to_string().contains("unknown key 'F13'"));
+    }
+}
@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+use tcod::Color;
+use tcod::map::Map as FovMap;
+
+use super::{DisplayObj, FOV_ALGO};
+
+/// Below this brightness a tile is too dark to make anything out.
+const MIN_VISIBLE_LIGHT: f32 = 0.1;
+
+/// Something that sheds light around it: a torch, a brazier, a glowing monster.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Light {
+    pub radius: i32,
+    pub color: Color,
+    pub intensity: f32,
+    /// turns left before the light goes out, or None if it burns forever
+    #[serde(default)]
+    pub duration: Option<u32>
+}
+
+/// The color and strength of the light falling on every tile of the map.
+pub struct LightMap {
+    light: Vec<Vec<[f32; 3]>>
+}
+
+impl LightMap {
+    pub fn new(width: i32, height: i32) -> Self {
+        LightMap {
+            light: vec![vec![[0.0; 3]; height as usize]; width as usize]
+        }
+    }
+
+    /// Recompute the light cast by every light source. `light_fov` is used
+    /// to cast each light's shadows and must hold the current map.
+    pub fn compute(&mut self, light_fov: &mut FovMap, objects: &[DisplayObj]) {
+        for column in self.light.iter_mut() {
+            for tile in column.iter_mut() {
+                *tile = [0.0; 3];
+            }
+        }
+
+        for object in objects {
+            if let Some(light) = object.light {
+                self.add_light(light_fov, object.x, object.y, &light);
+            }
+        }
+    }
+
+    fn add_light(&mut self, light_fov: &mut FovMap, x: i32, y: i32, light: &Light) {
+        light_fov.compute_fov(x, y, light.radius, true, FOV_ALGO);
+
+        let (width, height) = (self.light.len() as i32, self.light[0].len() as i32);
+        let color = [light.color.r as f32, light.color.g as f32, light.color.b as f32];
+        for tx in (x - light.radius).max(0)..(x + light.radius + 1).min(width) {
+            for ty in (y - light.radius).max(0)..(y + light.radius + 1).min(height) {
+                if !light_fov.is_in_fov(tx, ty) {
+                    continue;
+                }
+                // fades out towards the edge, but doesn't quite reach zero
+                let distance = (((tx - x).pow(2) + (ty - y).pow(2)) as f32).sqrt();
+                let falloff = 1.0 - (distance / (light.radius + 1) as f32).powi(2);
+                if falloff <= 0.0 {
+                    continue;
+                }
+
+                let tile = &mut self.light[tx as usize][ty as usize];
+                for channel in 0..3 {
+                    tile[channel] += color[channel] / 255.0 * light.intensity * falloff;
+                }
+            }
+        }
+    }
+
+    /// how much light falls on a tile, from 0 (pitch black) up
+    pub fn brightness(&self, x: i32, y: i32) -> f32 {
+        let [r, g, b] = self.light[x as usize][y as usize];
+        r.max(g).max(b)
+    }
+
+    pub fn is_lit(&self, x: i32, y: i32) -> bool {
+        self.brightness(x, y) >= MIN_VISIBLE_LIGHT
+    }
+
+    /// Color of a tile under its light: the `dark` color in the gloom, up to
+    /// the `lit` color tinted by the light at full brightness.
+    pub fn shade(&self, dark: Color, lit: Color, x: i32, y: i32) -> Color {
+        let brightness = self.brightness(x, y);
+        if brightness <= 0.0 {
+            return dark;
+        }
+
+        let [r, g, b] = self.light[x as usize][y as usize];
+        let tinted = Color {
+            r: (lit.r as f32 * r / brightness) as u8,
+            g: (lit.g as f32 * g / brightness) as u8,
+            b: (lit.b as f32 * b / brightness) as u8,
+        };
+        blend(dark, tinted, brightness.min(1.0))
+    }
+}
+
+fn blend(from: Color, to: Color, amount: f32) -> Color {
+    let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * amount) as u8;
+    Color {
+        r: mix(from.r, to.r),
+        g: mix(from.g, to.g),
+        b: mix(from.b, to.b),
+    }
+}
+
+/// A short-lived burst of light, e.g. from an explosion.
+pub fn flash(x: i32, y: i32, radius: i32, color: Color, turns: u32) -> DisplayObj {
+    let mut flash = DisplayObj::new(x, y, ' ', "flash", color, false);
+    flash.hidden = true;
+    flash.light = Some(Light {
+        radius: radius,
+        color: color,
+        intensity: 1.5,
+        duration: Some(turns)
+    });
+    flash
+}
+
+/// Count down lights that don't burn forever. Spent flashes are removed,
+/// anything else just goes dark.
+pub fn burn_down(objects: &mut Vec<DisplayObj>) {
+    for object in objects.iter_mut() {
+        if let Some(light) = object.light.as_mut() {
+            match light.duration {
+                Some(0) => object.light = None,
+                Some(turns) => light.duration = Some(turns - 1),
+                None => {}
+            }
+        }
+    }
+    objects.retain(|object| !(object.name == "flash" && object.light.is_none()));
+}
@@ -0,0 +1,129 @@
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use tcod::colors::*;
+
+use super::random;
+use super::conf::{LootDrop, ObjectConfiguration, TransitionTables};
+use super::affixes;
+use super::{from_dungeon_level, DisplayObj, Game, Map, Tcod, PLAYER_ID};
+
+/// Loot tables naming each other are only followed this deep, so a loop in
+/// the configuration can't hang the game.
+const MAX_LOOT_NESTING: u32 = 5;
+
+/// Roll a drop on its loot table, returning the items that came up.
+pub fn roll(drop: &LootDrop, tables: &TransitionTables, level: u32) -> Vec<DisplayObj> {
+    let mut rng = random::rng();
+    let chance = from_dungeon_level(&drop.chance, level);
+
+    let mut items = vec![];
+    for _ in 0..drop.rolls {
+        if rng.gen_range(0..100) >= chance {
+            continue;
+        }
+        if let Some(item) = roll_table(&drop.table, tables, level, 0) {
+            let mut item = item.as_object(0, 0);
+            affixes::enchant(&mut item, tables, level);
+            items.push(item);
+        }
+    }
+    items
+}
+
+/// Pick one weighted entry from a loot table, following nested tables down to an item.
+fn roll_table<'a>(name: &str, tables: &'a TransitionTables, level: u32, nesting: u32) -> Option<&'a ObjectConfiguration> {
+    if nesting > MAX_LOOT_NESTING {
+        return None;
+    }
+    let table = tables.find_loot_table(name)?;
+    let weights: Vec<u32> = table.entries
+        .iter()
+        .map(|entry| from_dungeon_level(&entry.weight, level))
+        .collect();
+    let choices = WeightedIndex::new(weights).ok()?;
+    let entry = &table.entries[choices.sample(&mut random::rng())];
+
+    match (entry.item.as_ref(), entry.table.as_ref()) {
+        (Some(item), _) => tables.find_item(item),
+        (None, Some(table)) => roll_table(table, tables, level, nesting + 1),
+        (None, None) => None
+    }
+}
+
+/// Drop whatever the dead were carrying where they fell, and roll their loot tables.
+pub fn drop_loot(tcod: &Tcod, game: &mut Game, objects: &mut Vec<DisplayObj>) {
+    let mut dropped = vec![];
+    for object in objects.iter_mut().filter(|object| !object.alive && object.fighter.is_none() && !object.container) {
+        let mut items: Vec<DisplayObj> = object.loot.drain(..).collect();
+        if let (Some(drop), Some(tables)) = (object.drops.take(), tcod.tables.as_ref()) {
+            items.extend(roll(&drop, tables, game.dungeon_level));
+        }
+
+        for mut item in items {
+            game.messages.add(format!("A {} falls to the ground.", item.name), LIGHT_YELLOW);
+            item.set_pos(object.x, object.y);
+            dropped.push(item);
+        }
+    }
+    objects.extend(dropped);
+}
+
+/// Open a chest, sarcophagus or other container next to the player. What's
+/// inside is rolled from its loot table and spills out at the player's feet.
+pub fn open_container(container_id: usize, tcod: &Tcod, game: &mut Game, objects: &mut Vec<DisplayObj>) {
+    let drop = objects[container_id].drops.take();
+    let name = objects[container_id].name.clone();
+    objects[container_id].container = false;
+    // once opened it's no longer in the way
+    objects[container_id].blocks = false;
+    objects[container_id].name = format!("opened {}", name);
+
+    let items = match (drop, tcod.tables.as_ref()) {
+        (Some(drop), Some(tables)) => roll(&drop, tables, game.dungeon_level),
+        _ => vec![]
+    };
+    if items.is_empty() {
+        game.messages.add(format!("You open the {}. It's empty.", name), LIGHT_GREY);
+        return;
+    }
+
+    let (player_x, player_y) = objects[PLAYER_ID].get_pos();
+    for mut item in items {
+        game.messages.add(format!("You open the {} and find a {}!", name, item.name), LIGHT_YELLOW);
+        item.set_pos(player_x, player_y);
+        objects.push(item);
+    }
+}
+
+/// Whether something solid placed on a tile could cut the level in two:
+/// the open tiles around it have to stay connected to each other without it.
+pub fn cuts_passage(x: i32, y: i32, map: &Map, objects: &[DisplayObj]) -> bool {
+    // monsters move out of the way, so only what stays put counts
+    let open = |&(x, y): &(i32, i32)| {
+        x >= 0 && y >= 0 && x < map.len() as i32 && y < map[0].len() as i32
+            && map[x as usize][y as usize].kind.is_traversable()
+            && !objects.iter().any(|object| object.blocks && object.fighter.is_none() && object.get_pos() == (x, y))
+    };
+    let mut neighbours: Vec<(i32, i32)> = (-1..=1)
+        .flat_map(|dx| (-1..=1).map(move |dy| (x + dx, y + dy)))
+        .filter(|&pos| pos != (x, y))
+        .filter(open)
+        .collect();
+
+    // gather everything reachable from one neighbour, anything left over is cut off
+    let mut connected = match neighbours.pop() {
+        Some(first) => vec![first],
+        None => return false
+    };
+    let mut i = 0;
+    while i < connected.len() {
+        let (cx, cy) = connected[i];
+        let (near, far): (Vec<(i32, i32)>, Vec<(i32, i32)>) = neighbours
+            .into_iter()
+            .partition(|&(nx, ny)| (nx - cx).abs() <= 1 && (ny - cy).abs() <= 1);
+        connected.extend(near);
+        neighbours = far;
+        i += 1;
+    }
+    !neighbours.is_empty()
+}
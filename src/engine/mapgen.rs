@@ -0,0 +1,755 @@
+use std::cmp;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::random;
+use super::{GameSettings, Map, Tile, TileKind};
+
+/// The algorithms available to lay out a dungeon level.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MapGenerator {
+    Rooms,
+    Bsp,
+    Caves,
+    Drunkard
+}
+
+// cellular automata caves
+const CAVE_WALL_CHANCE: f64 = 0.45;
+const CAVE_SMOOTHING_STEPS: u32 = 5;
+
+// drunkard's walk tunnels
+const DRUNKARD_FLOOR_RATIO: f32 = 0.4;
+const DRUNKARD_WALK_STEPS: u32 = 400;
+
+/// Tries at finding a free spot for a vault before giving up on it.
+const VAULT_PLACEMENT_TRIES: u32 = 50;
+
+const DOOR_CHANCE: f64 = 0.5;
+/// chance that a door is hidden as a secret door
+const SECRET_DOOR_CHANCE: f64 = 0.1;
+
+// terrain scattered over a level: number of patches and tiles per patch
+const WATER_PATCHES: (u32, u32) = (1, 4);
+const WATER_SIZE: (u32, u32) = (4, 14);
+const GRASS_PATCHES: (u32, u32) = (2, 5);
+const GRASS_SIZE: (u32, u32) = (6, 20);
+const RUBBLE_PATCHES: (u32, u32) = (2, 6);
+const RUBBLE_SIZE: (u32, u32) = (1, 4);
+const LAVA_PATCHES: (u32, u32) = (1, 3);
+const LAVA_SIZE: (u32, u32) = (3, 9);
+/// lava only flows this deep down
+const LAVA_MIN_LEVEL: u32 = 4;
+
+// the surface town: a walled square with shops along its north and south sides
+pub const TOWN_W: i32 = 48;
+pub const TOWN_H: i32 = 28;
+const SHOP_W: i32 = 8;
+const SHOP_H: i32 = 6;
+const SHOP_SPACING: i32 = 4;
+const TOWN_GRASS_PATCHES: (u32, u32) = (4, 8);
+
+/// Open levels are cut into square cells of this many room widths to make spawn areas.
+const AREA_CELL_ROOMS: i32 = 2;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Room {
+    x1: i32,
+    y1: i32,
+    x2: i32,
+    y2: i32
+}
+
+impl Room {
+    pub fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
+        Room {
+            x1: x,
+            y1: y,
+            x2: x + w,
+            y2: y + h,
+        }
+    }
+
+    pub fn center(&self) -> (i32, i32) {
+        let center_x = (self.x1 + self.x2) /2;
+        let center_y = (self.y1 + self.y2) /2;
+        (center_x, center_y)
+    }
+
+    /// whether a position lies inside the rectangle, walls included
+    pub fn contains(&self, (x, y): (i32, i32)) -> bool {
+        x >= self.x1 && x <= self.x2 && y >= self.y1 && y <= self.y2
+    }
+
+    pub fn intersects_with(&self, other: &Room) -> bool {
+        (self.x1 <= other.x2)
+            && (self.x2 >= other.x1)
+            && (self.y1 <= other.y2)
+            && (self.y2 >= other.y1)
+    }
+}
+
+/// A walkable part of a level that monsters and items can be spawned in,
+/// either a room or a chunk of an open cave.
+#[derive(Clone, Debug)]
+pub struct Area {
+    pub center: (i32, i32),
+    pub tiles: Vec<(i32, i32)>
+}
+
+impl Area {
+    pub fn from_room(room: &Room) -> Self {
+        let mut tiles = vec![];
+        for x in (room.x1 + 1)..room.x2 {
+            for y in (room.y1 + 1)..room.y2 {
+                tiles.push((x, y));
+            }
+        }
+        Area {
+            center: room.center(),
+            tiles: tiles
+        }
+    }
+
+    pub fn random_tile(&self) -> (i32, i32) {
+        self.tiles[random::rng().gen_range(0..self.tiles.len())]
+    }
+}
+
+/// Lay out a map with the given generator. Areas are returned so that the
+/// first and last ones are far apart, for the player start and the stairs.
+pub fn generate(generator: MapGenerator, game_settings: &GameSettings) -> (Map, Vec<Area>) {
+    let mut map = vec![vec![Tile::wall(); game_settings.map_h as usize]; game_settings.map_w as usize];
+
+    let areas = match generator {
+        MapGenerator::Rooms => {
+            let rooms = random_rooms(game_settings, &mut map);
+            add_doors(&rooms, &mut map);
+            rooms_to_areas(rooms)
+        }
+        MapGenerator::Bsp => {
+            let rooms = bsp_rooms(game_settings, &mut map);
+            add_doors(&rooms, &mut map);
+            rooms_to_areas(rooms)
+        }
+        MapGenerator::Caves => cellular_caves(game_settings, &mut map),
+        MapGenerator::Drunkard => drunkard_walk(game_settings, &mut map)
+    };
+
+    (map, areas)
+}
+
+/// Plain rooms in a grid, joined up one after another, for when the configured
+/// generator keeps failing. It's always connected, and uses the largest rooms
+/// that still give the level as many as it needs.
+pub fn fallback(game_settings: &GameSettings) -> (Map, Vec<Area>) {
+    let mut map = vec![vec![Tile::wall(); game_settings.map_h as usize]; game_settings.map_w as usize];
+    let (map_w, map_h) = (game_settings.map_w, game_settings.map_h);
+
+    // neighbouring rooms share a wall, so each takes its size plus one
+    let grid = |size: i32| ((map_w - 1) / (size + 1), (map_h - 1) / (size + 1));
+    let size = (game_settings.room_min_size as i32..=game_settings.room_max_size as i32)
+        .rev()
+        .find(|&size| {
+            let (cols, rows) = grid(size);
+            (cols * rows) as usize >= min_areas(game_settings)
+        })
+        .unwrap_or(game_settings.room_min_size as i32);
+    let (cols, rows) = grid(size);
+
+    let mut rooms: Vec<Room> = vec![];
+    'grid: for row in 0..rows {
+        for i in 0..cols {
+            // every other row runs backwards, so each room is next to the one before
+            let col = if row % 2 == 0 { i } else { cols - 1 - i };
+            if rooms.len() >= game_settings.max_rooms as usize {
+                break 'grid;
+            }
+            let room = Room::new(col * (size + 1), row * (size + 1), size + 1, size + 1);
+            create_room(room, &mut map);
+            if let Some(prev_room) = rooms.last() {
+                connect(prev_room.center(), room.center(), &mut map);
+            }
+            rooms.push(room);
+        }
+    }
+
+    // the stairs go in the room farthest from the start
+    let mut areas = rooms_to_areas(rooms);
+    let (sx, sy) = areas[0].center;
+    areas[1..].sort_by_key(|area| (area.center.0 - sx).pow(2) + (area.center.1 - sy).pow(2));
+    (map, areas)
+}
+
+/// Lay out the surface town in the middle of the map. The first area is the
+/// town square, followed by the floor inside each of the `shops` buildings.
+pub fn town(shops: usize, game_settings: &GameSettings) -> (Map, Vec<Area>) {
+    let mut map = vec![vec![Tile::wall(); game_settings.map_h as usize]; game_settings.map_w as usize];
+    let town_x = (game_settings.map_w - TOWN_W) / 2;
+    let town_y = (game_settings.map_h - TOWN_H) / 2;
+    create_room(Room::new(town_x, town_y, TOWN_W, TOWN_H), &mut map);
+
+    // buildings alternate between the north and south side, doors facing the square
+    let mut buildings = vec![];
+    for shop in 0..shops as i32 {
+        let x = town_x + SHOP_SPACING + (shop / 2) * (SHOP_W + SHOP_SPACING);
+        let north = shop % 2 == 0;
+        let y = if north { town_y + 2 } else { town_y + TOWN_H - 2 - SHOP_H };
+        let building = Room::new(x, y, SHOP_W, SHOP_H);
+
+        for bx in building.x1..building.x2 + 1 {
+            for by in building.y1..building.y2 + 1 {
+                map[bx as usize][by as usize] = Tile::wall();
+            }
+        }
+        create_room(building, &mut map);
+        let (door_x, _) = building.center();
+        let door_y = if north { building.y2 } else { building.y1 };
+        map[door_x as usize][door_y as usize] = Tile::new(TileKind::DoorClosed);
+        buildings.push(building);
+    }
+
+    // the square is whatever floor is left outside the buildings
+    let mut square = Area::from_room(&Room::new(town_x, town_y, TOWN_W, TOWN_H));
+    square.tiles.retain(|pos| !buildings.iter().any(|building| building.contains(*pos)));
+    let mut areas = vec![square];
+    scatter(&mut map, &areas, &[TileKind::Grass, TileKind::TallGrass], TOWN_GRASS_PATCHES, GRASS_SIZE);
+    areas.extend(buildings.iter().map(Area::from_room));
+
+    (map, areas)
+}
+
+fn rooms_to_areas(rooms: Vec<Room>) -> Vec<Area> {
+    rooms.iter().map(Area::from_room).collect()
+}
+
+fn create_room(room: Room, map: &mut Map) {
+    // go through the tiles in the rectangle and make them passable
+    for x in (room.x1 + 1)..room.x2 {
+        for y in (room.y1 + 1)..room.y2 {
+            map[x as usize][y as usize] = Tile::empty();
+        }
+    }
+}
+
+fn dig(x: i32, y: i32, map: &mut Map) {
+    // only solid rock is dug out, doors and terrain along the way are kept
+    if map[x as usize][y as usize].kind == TileKind::Wall {
+        map[x as usize][y as usize] = Tile::empty();
+    }
+}
+
+fn create_h_tunnel(x1: i32, x2: i32, y: i32, map: &mut Map) {
+    // horizontal tunnel. `min()` and `max()` are used in case `x1 > x2`
+    for x in cmp::min(x1, x2)..(cmp::max(x1, x2) + 1) {
+        dig(x, y, map);
+    }
+}
+
+fn create_v_tunnel(y1: i32, y2: i32, x: i32, map: &mut Map) {
+    // vertical tunnel
+    for y in cmp::min(y1, y2)..(cmp::max(y1, y2) + 1) {
+        dig(x, y, map);
+    }
+}
+
+/// connect two points with an L-shaped tunnel, bending in a random direction
+fn connect(from: (i32, i32), to: (i32, i32), map: &mut Map) {
+    let ((prev_x, prev_y), (new_x, new_y)) = (from, to);
+    if random::rng().gen() {
+        create_h_tunnel(prev_x, new_x, prev_y, map);
+        create_v_tunnel(prev_y, new_y, new_x, map);
+    } else {
+        create_v_tunnel(prev_y, new_y, prev_x, map);
+        create_h_tunnel(prev_x, new_x, new_y, map);
+    }
+}
+
+/// rooms a level needs: the configured minimum, and at least one for the
+/// player and another for the stairs
+fn min_areas(game_settings: &GameSettings) -> usize {
+    cmp::max(game_settings.min_rooms, 2)
+}
+
+/// The most areas a generator could ever lay out with these settings.
+fn max_areas(generator: MapGenerator, game_settings: &GameSettings) -> usize {
+    let (w, h) = (game_settings.map_w, game_settings.map_h);
+    match generator {
+        MapGenerator::Rooms => game_settings.max_rooms as usize,
+        MapGenerator::Bsp => {
+            // every leaf is at least as large as a room and its walls
+            let leaf = game_settings.room_max_size as i32 + 2;
+            ((w / leaf) * (h / leaf)) as usize
+        }
+        MapGenerator::Caves | MapGenerator::Drunkard => {
+            let cell = game_settings.room_max_size as i32 * AREA_CELL_ROOMS;
+            (((w + cell - 1) / cell) * ((h + cell - 1) / cell)) as usize
+        }
+    }
+}
+
+/// Check that a generator can lay out enough areas with these settings at
+/// all, so a level doesn't fail to generate in the middle of a game.
+pub fn check_generator(generator: MapGenerator, game_settings: &GameSettings) -> Result<(), String> {
+    let (most, needed) = (max_areas(generator, game_settings), min_areas(game_settings));
+    if most < needed {
+        return Err(format!(
+            "the {:?} generator makes at most {} rooms on a {}x{} map with room_max_size {} and max_rooms {}, levels need {}",
+            generator, most, game_settings.map_w, game_settings.map_h,
+            game_settings.room_max_size, game_settings.max_rooms, needed
+        ));
+    }
+    Ok(())
+}
+
+/// Check that a generated level is playable: enough areas, and stairs that
+/// are far enough from the player and reachable from where they start.
+pub fn validate(map: &Map, areas: &[Area], game_settings: &GameSettings) -> Result<(), String> {
+    if areas.len() < min_areas(game_settings) {
+        return Err(format!("only {} rooms, needs {}", areas.len(), min_areas(game_settings)));
+    }
+
+    let start = areas[0].center;
+    let stairs = areas[areas.len() - 1].center;
+    let distance = (((stairs.0 - start.0).pow(2) + (stairs.1 - start.1).pow(2)) as f32).sqrt();
+    if distance < game_settings.min_stairs_distance as f32 {
+        return Err(format!(
+            "stairs are {:.0} tiles from the start, needs {}",
+            distance, game_settings.min_stairs_distance
+        ));
+    }
+
+    if !flood_fill(map, start).contains(&stairs) {
+        return Err("stairs can't be reached from the start".into());
+    }
+    Ok(())
+}
+
+/// Find a random spot where a vault of the given size fits inside the map
+/// without covering other vaults or the first or last area, where the
+/// player and stairs go. The vault keeps two tiles from the map edge, so
+/// the tunnel from an entrance on its side never starts on the border.
+pub fn find_vault_spot(map: &Map, areas: &[Area], taken: &[Room], w: i32, h: i32) -> Option<(i32, i32)> {
+    let (map_w, map_h) = (map.len() as i32, map[0].len() as i32);
+    if w + 4 >= map_w || h + 4 >= map_h || areas.is_empty() {
+        return None;
+    }
+
+    let reserved = [&areas[0], &areas[areas.len() - 1]];
+    for _ in 0..VAULT_PLACEMENT_TRIES {
+        let x = random::rng().gen_range(2..map_w - w - 2);
+        let y = random::rng().gen_range(2..map_h - h - 2);
+        let vault = Room::new(x - 1, y - 1, w + 1, h + 1);
+
+        let covers_reserved = reserved
+            .iter()
+            .any(|area| area.tiles.iter().any(|&tile| vault.contains(tile)));
+        let covers_vault = taken.iter().any(|other| vault.intersects_with(other));
+        if !covers_reserved && !covers_vault {
+            return Some((x, y));
+        }
+    }
+    None
+}
+
+/// dig a tunnel from a position to the closest area outside the given rectangle
+pub fn connect_to_nearest_area(from: (i32, i32), exclude: Room, areas: &[Area], map: &mut Map) {
+    let nearest = areas
+        .iter()
+        .filter(|area| !exclude.contains(area.center))
+        .min_by_key(|area| (area.center.0 - from.0).pow(2) + (area.center.1 - from.1).pow(2));
+    if let Some(area) = nearest {
+        connect(from, area.center, map);
+    }
+}
+
+/// Put doors in the single-tile gaps where corridors break through room walls,
+/// some of them hidden.
+fn add_doors(rooms: &[Room], map: &mut Map) {
+    for room in rooms {
+        let mut wall_tiles = vec![];
+        for x in (room.x1 + 1)..room.x2 {
+            wall_tiles.push(((x, room.y1), (1, 0)));
+            wall_tiles.push(((x, room.y2), (1, 0)));
+        }
+        for y in (room.y1 + 1)..room.y2 {
+            wall_tiles.push(((room.x1, y), (0, 1)));
+            wall_tiles.push(((room.x2, y), (0, 1)));
+        }
+
+        for ((x, y), (dx, dy)) in wall_tiles {
+            let is_gap = map[x as usize][y as usize].kind == TileKind::Floor
+                && map[(x - dx) as usize][(y - dy) as usize].kind == TileKind::Wall
+                && map[(x + dx) as usize][(y + dy) as usize].kind == TileKind::Wall;
+            if is_gap && random::rng().gen_bool(DOOR_CHANCE) {
+                let kind = if random::rng().gen_bool(SECRET_DOOR_CHANCE) {
+                    TileKind::SecretDoor
+                } else {
+                    TileKind::DoorClosed
+                };
+                map[x as usize][y as usize] = Tile::new(kind);
+            }
+        }
+    }
+}
+
+/// Scatter patches of water, grass, rubble and, deeper down, lava over the
+/// floor of the level. The first and last areas are left alone, so the
+/// player start and the stairs stay on plain ground.
+pub fn decorate(map: &mut Map, areas: &[Area], level: u32) {
+    if areas.len() < 3 {
+        return;
+    }
+    let middle = &areas[1..areas.len() - 1];
+
+    scatter(map, middle, &[TileKind::ShallowWater], WATER_PATCHES, WATER_SIZE);
+    scatter(map, middle, &[TileKind::Grass, TileKind::TallGrass], GRASS_PATCHES, GRASS_SIZE);
+    scatter(map, middle, &[TileKind::Rubble], RUBBLE_PATCHES, RUBBLE_SIZE);
+    if level >= LAVA_MIN_LEVEL {
+        scatter(map, middle, &[TileKind::Lava], LAVA_PATCHES, LAVA_SIZE);
+    }
+}
+
+/// Grow random patches of terrain over plain floor, each tile picking one of the given kinds.
+fn scatter(map: &mut Map, areas: &[Area], kinds: &[TileKind], patches: (u32, u32), size: (u32, u32)) {
+    let mut rng = random::rng();
+    for _ in 0..rng.gen_range(patches.0..patches.1 + 1) {
+        let area = &areas[rng.gen_range(0..areas.len())];
+        if area.tiles.is_empty() {
+            continue;
+        }
+        let (mut x, mut y) = area.random_tile();
+        let tiles = rng.gen_range(size.0..size.1 + 1);
+
+        // wander around from the starting tile, covering floor as it goes
+        let mut covered = 0;
+        for _ in 0..tiles * 4 {
+            if covered >= tiles {
+                break;
+            }
+            if map[x as usize][y as usize].kind == TileKind::Floor {
+                map[x as usize][y as usize] = Tile::new(kinds[rng.gen_range(0..kinds.len())]);
+                covered += 1;
+            }
+            let (nx, ny) = (x + rng.gen_range(-1..2), y + rng.gen_range(-1..2));
+            if map[nx as usize][ny as usize].kind != TileKind::Wall {
+                x = nx;
+                y = ny;
+            }
+        }
+    }
+}
+
+/// scatter non-overlapping rooms, each linked to the previous one
+fn random_rooms(game_settings: &GameSettings, map: &mut Map) -> Vec<Room> {
+    let mut rooms: Vec<Room> = vec![];
+    for _ in 0..game_settings.max_rooms {
+        let w = random::rng().gen_range(game_settings.room_min_size..game_settings.room_max_size+1) as i32;
+        let h = random::rng().gen_range(game_settings.room_min_size..game_settings.room_max_size+1) as i32;
+
+        let x = random::rng().gen_range(0..game_settings.map_w - w );
+        let y = random::rng().gen_range(0..game_settings.map_h - h);
+
+        let new_room = Room::new(x, y, w, h);
+
+        // run through the other rooms and see if they intersect with this one
+        let failed = rooms
+            .iter()
+            .any(|other_room| new_room.intersects_with(other_room));
+
+        if !failed {
+            create_room(new_room, map);
+
+            if let Some(prev_room) = rooms.last() {
+                connect(prev_room.center(), new_room.center(), map);
+            }
+            rooms.push(new_room);
+        }
+    }
+    rooms
+}
+
+/// Recursively split the map in two until the pieces are about the size of
+/// a room, put one room in each piece and connect sibling pieces together.
+fn bsp_rooms(game_settings: &GameSettings, map: &mut Map) -> Vec<Room> {
+    let mut rooms = vec![];
+    let whole_map = Room::new(0, 0, game_settings.map_w, game_settings.map_h);
+    split_leaf(whole_map, game_settings, map, &mut rooms);
+    rooms
+}
+
+/// Fill a partition of the map, returning the room its corridors should attach to.
+fn split_leaf(leaf: Room, game_settings: &GameSettings, map: &mut Map, rooms: &mut Vec<Room>) -> Room {
+    // a leaf must fit the largest room plus its walls
+    let min_leaf = game_settings.room_max_size as i32 + 2;
+    let (w, h) = (leaf.x2 - leaf.x1, leaf.y2 - leaf.y1);
+    let can_split_w = w >= min_leaf * 2;
+    let can_split_h = h >= min_leaf * 2;
+
+    if !can_split_w && !can_split_h {
+        let room = leaf_room(leaf, game_settings);
+        create_room(room, map);
+        rooms.push(room);
+        return room;
+    }
+
+    // prefer cutting across the longer side to keep the pieces square-ish
+    let split_vertically = match (can_split_w, can_split_h) {
+        (true, false) => true,
+        (false, true) => false,
+        _ if w as f32 > h as f32 * 1.25 => true,
+        _ if h as f32 > w as f32 * 1.25 => false,
+        _ => random::rng().gen()
+    };
+
+    let (first, second) = if split_vertically {
+        let split = random::rng().gen_range(min_leaf..w - min_leaf + 1);
+        (Room::new(leaf.x1, leaf.y1, split, h), Room::new(leaf.x1 + split, leaf.y1, w - split, h))
+    } else {
+        let split = random::rng().gen_range(min_leaf..h - min_leaf + 1);
+        (Room::new(leaf.x1, leaf.y1, w, split), Room::new(leaf.x1, leaf.y1 + split, w, h - split))
+    };
+
+    let first_room = split_leaf(first, game_settings, map, rooms);
+    let second_room = split_leaf(second, game_settings, map, rooms);
+    connect(first_room.center(), second_room.center(), map);
+
+    if random::rng().gen() {
+        first_room
+    } else {
+        second_room
+    }
+}
+
+/// a randomly sized and placed room that fits inside the leaf
+fn leaf_room(leaf: Room, game_settings: &GameSettings) -> Room {
+    let max_w = cmp::min(game_settings.room_max_size as i32, leaf.x2 - leaf.x1 - 1);
+    let max_h = cmp::min(game_settings.room_max_size as i32, leaf.y2 - leaf.y1 - 1);
+    let w = random::rng().gen_range(game_settings.room_min_size as i32..max_w + 1);
+    let h = random::rng().gen_range(game_settings.room_min_size as i32..max_h + 1);
+
+    let x = random::rng().gen_range(leaf.x1..leaf.x2 - w);
+    let y = random::rng().gen_range(leaf.y1..leaf.y2 - h);
+    Room::new(x, y, w, h)
+}
+
+/// Fill the map with random noise and smooth it into caves, keeping only
+/// the largest connected cave so every part of the level is reachable.
+fn cellular_caves(game_settings: &GameSettings, map: &mut Map) -> Vec<Area> {
+    let (w, h) = (game_settings.map_w, game_settings.map_h);
+    let mut rng = random::rng();
+
+    // the outer edge stays solid rock
+    for x in 1..w - 1 {
+        for y in 1..h - 1 {
+            if !rng.gen_bool(CAVE_WALL_CHANCE) {
+                map[x as usize][y as usize] = Tile::empty();
+            }
+        }
+    }
+
+    for _ in 0..CAVE_SMOOTHING_STEPS {
+        let previous = map.clone();
+        for x in 1..w - 1 {
+            for y in 1..h - 1 {
+                let walls = count_neighbour_walls(&previous, x, y);
+                let wall = if previous[x as usize][y as usize].blocked() {
+                    walls >= 4
+                } else {
+                    walls >= 5
+                };
+                map[x as usize][y as usize] = if wall { Tile::wall() } else { Tile::empty() };
+            }
+        }
+    }
+
+    keep_largest_region(map);
+    areas_from_floor(game_settings, map)
+}
+
+fn count_neighbour_walls(map: &Map, x: i32, y: i32) -> u32 {
+    let mut walls = 0;
+    for dx in -1..2 {
+        for dy in -1..2 {
+            if (dx, dy) != (0, 0) && map[(x + dx) as usize][(y + dy) as usize].blocked() {
+                walls += 1;
+            }
+        }
+    }
+    walls
+}
+
+/// Stagger randomly through solid rock, each walker starting from ground
+/// that is already dug out, until enough of the map is open.
+fn drunkard_walk(game_settings: &GameSettings, map: &mut Map) -> Vec<Area> {
+    let (w, h) = (game_settings.map_w, game_settings.map_h);
+    let mut rng = random::rng();
+
+    let target = ((w - 2) * (h - 2)) as f32 * DRUNKARD_FLOOR_RATIO;
+    let mut dug = vec![(w / 2, h / 2)];
+    map[(w / 2) as usize][(h / 2) as usize] = Tile::empty();
+
+    while (dug.len() as f32) < target {
+        let (mut x, mut y) = dug[rng.gen_range(0..dug.len())];
+        for _ in 0..DRUNKARD_WALK_STEPS {
+            match rng.gen_range(0..4) {
+                0 => x += 1,
+                1 => x -= 1,
+                2 => y += 1,
+                _ => y -= 1
+            }
+            x = cmp::max(1, cmp::min(x, w - 2));
+            y = cmp::max(1, cmp::min(y, h - 2));
+
+            if map[x as usize][y as usize].blocked() {
+                map[x as usize][y as usize] = Tile::empty();
+                dug.push((x, y));
+            }
+        }
+    }
+
+    areas_from_floor(game_settings, map)
+}
+
+/// all the walkable tiles connected to the start position
+pub fn flood_fill(map: &Map, start: (i32, i32)) -> Vec<(i32, i32)> {
+    let (w, h) = (map.len() as i32, map[0].len() as i32);
+    let mut seen = vec![vec![false; h as usize]; w as usize];
+    let mut region = vec![];
+    let mut stack = vec![start];
+    seen[start.0 as usize][start.1 as usize] = true;
+
+    while let Some((x, y)) = stack.pop() {
+        region.push((x, y));
+        for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx < 0 || ny < 0 || nx >= w || ny >= h {
+                continue;
+            }
+            if !seen[nx as usize][ny as usize] && map[nx as usize][ny as usize].kind.is_traversable() {
+                seen[nx as usize][ny as usize] = true;
+                stack.push((nx, ny));
+            }
+        }
+    }
+    region
+}
+
+/// wall up every cave pocket except the biggest one
+fn keep_largest_region(map: &mut Map) {
+    let (w, h) = (map.len() as i32, map[0].len() as i32);
+    let mut seen = vec![vec![false; h as usize]; w as usize];
+    let mut regions = vec![];
+
+    for x in 0..w {
+        for y in 0..h {
+            if !seen[x as usize][y as usize] && !map[x as usize][y as usize].blocked() {
+                let region = flood_fill(map, (x, y));
+                for &(rx, ry) in &region {
+                    seen[rx as usize][ry as usize] = true;
+                }
+                regions.push(region);
+            }
+        }
+    }
+
+    regions.sort_by_key(|region| cmp::Reverse(region.len()));
+    for region in regions.iter().skip(1) {
+        for &(x, y) in region {
+            map[x as usize][y as usize] = Tile::wall();
+        }
+    }
+}
+
+/// Cut the open ground of a cave-like map into square chunks to spawn in.
+/// The start area is picked at random and the rest are ordered by distance from it.
+fn areas_from_floor(game_settings: &GameSettings, map: &Map) -> Vec<Area> {
+    let cell = game_settings.room_max_size as i32 * AREA_CELL_ROOMS;
+    let min_tiles = (game_settings.room_min_size * game_settings.room_min_size / 2) as usize;
+
+    let mut areas = vec![];
+    for cell_x in (0..game_settings.map_w).step_by(cell as usize) {
+        for cell_y in (0..game_settings.map_h).step_by(cell as usize) {
+            let mut tiles = vec![];
+            for x in cell_x..cmp::min(cell_x + cell, game_settings.map_w) {
+                for y in cell_y..cmp::min(cell_y + cell, game_settings.map_h) {
+                    if !map[x as usize][y as usize].blocked() {
+                        tiles.push((x, y));
+                    }
+                }
+            }
+            if tiles.len() < min_tiles {
+                continue;
+            }
+
+            // the walkable tile nearest to the middle of the chunk
+            let count = tiles.len() as i32;
+            let mean_x = tiles.iter().map(|t| t.0).sum::<i32>() / count;
+            let mean_y = tiles.iter().map(|t| t.1).sum::<i32>() / count;
+            let center = *tiles
+                .iter()
+                .min_by_key(|t| (t.0 - mean_x).pow(2) + (t.1 - mean_y).pow(2))
+                .unwrap();
+
+            areas.push(Area { center: center, tiles: tiles });
+        }
+    }
+
+    if !areas.is_empty() {
+        let start = random::rng().gen_range(0..areas.len());
+        areas.swap(0, start);
+        let (sx, sy) = areas[0].center;
+        areas[1..].sort_by_key(|area| (area.center.0 - sx).pow(2) + (area.center.1 - sy).pow(2));
+    }
+    areas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the level's layout, as far as the map and its areas go
+    fn layout(generator: MapGenerator) -> (Vec<Vec<TileKind>>, Vec<(i32, i32)>) {
+        let (map, areas) = generate(generator, &GameSettings::new());
+        let kinds = map.iter().map(|column| column.iter().map(|tile| tile.kind).collect()).collect();
+        (kinds, areas.iter().map(|area| area.center).collect())
+    }
+
+    #[test]
+    fn same_seed_builds_the_same_level() {
+        for generator in [MapGenerator::Rooms, MapGenerator::Bsp, MapGenerator::Caves, MapGenerator::Drunkard].iter() {
+            let seeded_layout = || {
+                random::seed(42);
+                layout(*generator)
+            };
+            assert!(seeded_layout() == seeded_layout(), "{:?}", generator);
+        }
+    }
+
+    #[test]
+    fn fallback_levels_are_valid() {
+        let mut game_settings = GameSettings::new();
+        let (map, areas) = fallback(&game_settings);
+        assert_eq!(validate(&map, &areas, &game_settings), Ok(()));
+
+        // a small map has to make do with smaller rooms
+        game_settings.map_w = 30;
+        game_settings.map_h = 30;
+        game_settings.min_stairs_distance = 10;
+        let (map, areas) = fallback(&game_settings);
+        assert_eq!(validate(&map, &areas, &game_settings), Ok(()));
+    }
+
+    #[test]
+    fn a_level_is_the_same_whatever_came_before() {
+        let level_layout = |rolls_before: u32| {
+            random::seed(1);
+            for _ in 0..rolls_before {
+                random::rng().gen::<u32>();
+            }
+            random::for_level(42, 5, || layout(MapGenerator::Bsp))
+        };
+        assert!(level_layout(0) == level_layout(250));
+    }
+}
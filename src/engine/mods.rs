@@ -0,0 +1,218 @@
+use serde::Deserialize;
+use serde_json::{Map as JsonMap, Value};
+use std::error::Error;
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+/// Lists the mods to load, in order, and the directories to look for them in.
+/// It sits next to the settings file, and its directories are relative to it.
+pub const MODS_FILE: &str = "mods.json";
+/// each mod directory holds a settings file like the base game's, with just what it adds or changes
+const PACK_FILE: &str = "settings.json";
+
+/// Sections whose entries are matched up by a field, so a mod can add to them
+/// or change a single entry. Any other section a mod gives replaces the base one.
+const KEYED_SECTIONS: [(&str, &str); 9] = [
+    ("monsters", "name"),
+    ("items", "name"),
+    ("traps", "name"),
+    ("features", "name"),
+    ("uniques", "name"),
+    ("loot_tables", "name"),
+    ("affixes", "name"),
+    ("vaults", "name"),
+    ("rarities", "rarity")
+];
+
+#[derive(Debug, Default, Deserialize)]
+struct ModList {
+    #[serde(default)]
+    directories: Vec<String>,
+    /// names of the mod directories to load, later ones winning over earlier ones
+    #[serde(default)]
+    load_order: Vec<String>
+}
+
+/// The data of the base game or of a mod, as read from its settings file.
+#[derive(Debug)]
+pub struct DataPack {
+    pub name: String,
+    pub path: String,
+    pub json: String
+}
+
+impl DataPack {
+    fn read(name: &str, path: &str) -> Result<DataPack, Box<dyn Error>> {
+        let mut json = String::new();
+        let mut file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        file.read_to_string(&mut json)?;
+        Ok(DataPack { name: name.into(), path: path.into(), json: json })
+    }
+}
+
+/// The base game's settings file, followed by every mod in the load order.
+/// A mod that is missing or can't be read is left out, and what's wrong with
+/// it returned alongside, so it doesn't take the base game down with it.
+pub fn load_data_packs(settings_file: &str) -> Result<(Vec<DataPack>, Vec<String>), Box<dyn Error>> {
+    let mut packs = vec![DataPack::read("base", settings_file)?];
+    let mut problems = vec![];
+
+    let base_directory = Path::new(settings_file).parent().unwrap_or_else(|| Path::new(""));
+    let mods_file = base_directory.join(MODS_FILE);
+    let mods = match read_mod_list(&mods_file) {
+        Ok(mods) => mods,
+        Err(e) => return Ok((packs, vec![e.to_string()]))
+    };
+    let directories: Vec<PathBuf> = mods.directories
+        .iter()
+        .map(|directory| base_directory.join(directory))
+        .collect();
+    for name in &mods.load_order {
+        match load_mod(name, &directories) {
+            Ok(pack) => packs.push(pack),
+            Err(e) => problems.push(e.to_string())
+        }
+    }
+    Ok((packs, problems))
+}
+
+fn load_mod(name: &str, directories: &[PathBuf]) -> Result<DataPack, Box<dyn Error>> {
+    let path = directories
+        .iter()
+        .map(|directory| directory.join(name).join(PACK_FILE))
+        .find(|path| path.is_file())
+        .ok_or_else(|| format!("mod '{}' isn't in any of {:?}", name, directories))?;
+    let pack = DataPack::read(name, &path.to_string_lossy())?;
+
+    // a mod that can't be merged would keep every table from loading
+    match serde_json::from_str::<Value>(&pack.json).map_err(|e| format!("{}: {}", pack.path, e))? {
+        Value::Object(_) => Ok(pack),
+        _ => Err(format!("{}: expected an object of settings", pack.path).into())
+    }
+}
+
+/// Without a mods file, no mods are loaded.
+fn read_mod_list(path: &Path) -> Result<ModList, Box<dyn Error>> {
+    let mut json = String::new();
+    match File::open(path) {
+        Ok(mut file) => file.read_to_string(&mut json)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(ModList::default()),
+        Err(e) => return Err(format!("{}: {}", path.display(), e).into())
+    };
+    Ok(serde_json::from_str(&json).map_err(|e| format!("{}: {}", path.display(), e))?)
+}
+
+/// Lay the packs over each other in order. Entries of the keyed sections with
+/// the same name are merged, so a mod only needs to give the fields it changes.
+pub fn merge(packs: &[DataPack]) -> Result<Value, Box<dyn Error>> {
+    let mut merged = JsonMap::new();
+    for pack in packs {
+        let sections = match serde_json::from_str(&pack.json).map_err(|e| format!("{}: {}", pack.path, e))? {
+            Value::Object(sections) => sections,
+            _ => return Err(format!("{}: expected an object of settings", pack.path).into())
+        };
+
+        for (section, value) in sections {
+            let base = merged.entry(section.clone()).or_insert(Value::Null);
+            match KEYED_SECTIONS.iter().find(|(name, _)| *name == section) {
+                Some((_, key)) => merge_entries(base, value, key),
+                None => merge_values(base, value)
+            }
+        }
+    }
+    Ok(Value::Object(merged))
+}
+
+fn merge_entries(base: &mut Value, entries: Value, key: &str) {
+    let (base_entries, entries) = match (base, entries) {
+        (Value::Array(base_entries), Value::Array(entries)) => (base_entries, entries),
+        (base, entries) => return *base = entries
+    };
+    for entry in entries {
+        let existing = entry.get(key).and_then(|id| {
+            base_entries.iter_mut().find(|base_entry| base_entry.get(key) == Some(id))
+        });
+        match existing {
+            Some(existing) => merge_values(existing, entry),
+            None => base_entries.push(entry)
+        }
+    }
+}
+
+/// Objects merge field by field, anything else is replaced.
+fn merge_values(base: &mut Value, value: Value) {
+    match (base, value) {
+        (Value::Object(base_fields), Value::Object(fields)) => {
+            for (field, value) in fields {
+                merge_values(base_fields.entry(field).or_insert(Value::Null), value);
+            }
+        }
+        (base, value) => *base = value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn pack(name: &str, json: Value) -> DataPack {
+        DataPack { name: name.into(), path: format!("{}/settings.json", name), json: json.to_string() }
+    }
+
+    fn base() -> DataPack {
+        pack("base", json!({
+            "game": { "map_w": 120, "map_h": 80 },
+            "max_monsters": [ { "level": 1, "value": 2 } ],
+            "monsters": [
+                { "name": "Rat", "char": "r", "fighter": { "hp": 15, "base_power": 3 } },
+                { "name": "Slime", "char": "s" }
+            ]
+        }))
+    }
+
+    #[test]
+    fn entries_with_the_same_name_are_merged() {
+        let tougher_rats = pack("tougher_rats", json!({ "monsters": [ { "name": "Rat", "fighter": { "hp": 20 } } ] }));
+        let merged = merge(&[base(), tougher_rats]).unwrap();
+        assert_eq!(merged["monsters"][0], json!({ "name": "Rat", "char": "r", "fighter": { "hp": 20, "base_power": 3 } }));
+        assert_eq!(merged["monsters"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn new_entries_are_appended() {
+        let bats = pack("bats", json!({ "monsters": [ { "name": "Bat", "char": "b" } ] }));
+        let merged = merge(&[base(), bats]).unwrap();
+        let names: Vec<&str> = merged["monsters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|monster| monster["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["Rat", "Slime", "Bat"]);
+    }
+
+    #[test]
+    fn later_mods_win() {
+        let first = pack("first", json!({ "monsters": [ { "name": "Rat", "char": "R" } ], "game": { "map_w": 100 } }));
+        let second = pack("second", json!({ "monsters": [ { "name": "Rat", "char": "q" } ], "game": { "map_w": 90 } }));
+        let merged = merge(&[base(), first, second]).unwrap();
+        assert_eq!(merged["monsters"][0]["char"], json!("q"));
+        assert_eq!(merged["game"], json!({ "map_w": 90, "map_h": 80 }));
+    }
+
+    #[test]
+    fn other_sections_are_replaced() {
+        let busy = pack("busy", json!({ "max_monsters": [ { "level": 1, "value": 5 } ] }));
+        let merged = merge(&[base(), busy]).unwrap();
+        assert_eq!(merged["max_monsters"], json!([ { "level": 1, "value": 5 } ]));
+    }
+
+    #[test]
+    fn broken_packs_name_their_file() {
+        let broken = DataPack { name: "broken".into(), path: "broken/settings.json".into(), json: "{ \"monsters\": [".into() };
+        let error = merge(&[base(), broken]).unwrap_err().to_string();
+        assert!(error.starts_with("broken/settings.json:"), "{}", error);
+    }
+}
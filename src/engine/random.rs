@@ -0,0 +1,67 @@
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use std::cell::RefCell;
+
+thread_local! {
+    static GAME_RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// The random number generator behind everything the game rolls. It can be
+/// seeded, so a run can be played again from the same dungeon.
+#[derive(Clone, Copy, Debug)]
+pub struct GameRng;
+
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        GAME_RNG.with(|rng| rng.borrow_mut().next_u32())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        GAME_RNG.with(|rng| rng.borrow_mut().next_u64())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        GAME_RNG.with(|rng| rng.borrow_mut().fill_bytes(dest))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        GAME_RNG.with(|rng| rng.borrow_mut().try_fill_bytes(dest))
+    }
+}
+
+pub fn rng() -> GameRng {
+    GameRng
+}
+
+/// Start the generator over from a seed.
+pub fn seed(seed: u64) {
+    GAME_RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+/// Run level generation on a generator of its own, seeded from the run's seed
+/// and the depth, so a level comes out the same whatever the player did before
+/// reaching it. Everything else goes on rolling where it left off.
+pub fn for_level<T, F: FnOnce() -> T>(run_seed: u64, level: u32, generate: F) -> T {
+    // spread the depths out, so neighbouring levels don't get similar seeds
+    let level_seed = run_seed ^ (level as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    let game_rng = GAME_RNG.with(|rng| rng.replace(StdRng::seed_from_u64(level_seed)));
+    let result = generate();
+    GAME_RNG.with(|rng| *rng.borrow_mut() = game_rng);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn generating_a_level_leaves_the_game_rolls_alone() {
+        seed(1);
+        let expected: u64 = rng().gen();
+
+        seed(1);
+        for_level(42, 3, || rng().gen::<u64>());
+        assert_eq!(rng().gen::<u64>(), expected);
+    }
+}
@@ -0,0 +1,234 @@
+use serde::{Deserialize, Serialize};
+use tcod::colors::*;
+
+use super::affixes;
+use super::conf::{ObjectConfiguration, TransitionTables};
+use super::lighting::Light;
+use super::ui::{colored_menu, inventory_menu, menu, msgbox};
+use super::{choose_matching, DisplayObj, Game, Item, Tcod, MAX_INV_SPACE};
+
+/// The town lies above the first level of the crypt.
+pub const TOWN_LEVEL: u32 = 0;
+
+const SHOP_STOCK_SIZE: u32 = 8;
+/// the most a shop holds, as many as its menu can list
+const MAX_SHOP_STOCK: usize = 26;
+/// shops sell what could be found this deep in the crypt
+const SHOP_STOCK_LEVEL: u32 = 4;
+/// percent of its price a shopkeeper pays for an item
+const SELL_PERCENT: u32 = 50;
+const SHOP_WIDTH: i32 = 50;
+/// what the smith charges for each point of durability restored
+const REPAIR_COST: u32 = 1;
+
+const LAMP_RADIUS: i32 = 8;
+const LAMP_COLOR: Color = Color { r: 255, g: 190, b: 110 };
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ShopKind {
+    General,
+    Armoury,
+    Alchemist
+}
+
+impl ShopKind {
+    pub const ALL: [ShopKind; 3] = [ShopKind::General, ShopKind::Armoury, ShopKind::Alchemist];
+
+    fn keeper_name(&self) -> &'static str {
+        match self {
+            ShopKind::General => "shopkeeper",
+            ShopKind::Armoury => "smith",
+            ShopKind::Alchemist => "alchemist"
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            ShopKind::General => LIGHT_AMBER,
+            ShopKind::Armoury => LIGHT_BLUE,
+            ShopKind::Alchemist => LIGHT_PURPLE
+        }
+    }
+
+    /// whether this kind of shop stocks an item from the spawn tables
+    fn sells(&self, item: &ObjectConfiguration) -> bool {
+        if item.price == 0 || item.item.is_none() || item.item == Some(Item::Gold) {
+            return false;
+        }
+        match self {
+            ShopKind::General => true,
+            ShopKind::Armoury => item.equipment.is_some(),
+            ShopKind::Alchemist => item.equipment.is_none() && item.item != Some(Item::Key) && item.item != Some(Item::Food)
+        }
+    }
+}
+
+/// What a shopkeeper has for sale.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Shop {
+    pub kind: ShopKind,
+    pub stock: Vec<DisplayObj>
+}
+
+/// A friendly shopkeeper standing in their shop, with stock picked from the item tables.
+pub fn shopkeeper(kind: ShopKind, (x, y): (i32, i32), tables: Option<&TransitionTables>) -> DisplayObj {
+    let mut keeper = DisplayObj::new(x, y, '@', kind.keeper_name(), kind.color(), true);
+    keeper.always_visible = true;
+    keeper.light = Some(Light {
+        radius: LAMP_RADIUS / 2,
+        color: LAMP_COLOR,
+        intensity: 1.0,
+        duration: None
+    });
+
+    let mut stock = vec![];
+    if let Some(tables) = tables {
+        for _ in 0..SHOP_STOCK_SIZE {
+            if let Some(item) = choose_matching(&tables.items, SHOP_STOCK_LEVEL, |item| kind.sells(item)) {
+                let mut item = item.as_object(0, 0);
+                affixes::enchant(&mut item, tables, SHOP_STOCK_LEVEL);
+                // shopkeepers know a cursed item when they see one
+                item.identified = true;
+                stock.push(item);
+            }
+        }
+    }
+    keeper.shop = Some(Shop { kind: kind, stock: stock });
+    keeper
+}
+
+/// a street lamp lighting up the town square
+pub fn lamp_post(x: i32, y: i32) -> DisplayObj {
+    let mut lamp = DisplayObj::new(x, y, 'Y', "lamp post", LIGHT_GREY, true);
+    lamp.always_visible = true;
+    lamp.light = Some(Light {
+        radius: LAMP_RADIUS,
+        color: LAMP_COLOR,
+        intensity: 1.0,
+        duration: None
+    });
+    lamp
+}
+
+/// price a shopkeeper pays for an item
+fn sell_price(item: &DisplayObj) -> u32 {
+    item.value * SELL_PERCENT / 100
+}
+
+/// Talk to a shopkeeper, buying and selling until the player leaves.
+pub fn trade(keeper_id: usize, tcod: &mut Tcod, game: &mut Game, objects: &mut [DisplayObj]) {
+    let keeper = &mut objects[keeper_id];
+    loop {
+        let header = format!(
+            "The {} greets you. You have {} gold.\n",
+            keeper.name, game.gold
+        );
+        // the smith also mends worn equipment
+        let smith = keeper.shop.as_ref().map_or(false, |shop| shop.kind == ShopKind::Armoury);
+        let options: &[&str] = if smith {
+            &["Buy", "Sell", "Repair", "Leave"]
+        } else {
+            &["Buy", "Sell", "Leave"]
+        };
+        let choice = menu(&header, options, SHOP_WIDTH, &mut tcod.root, &game.game_settings);
+        match choice {
+            Some(0) => buy(keeper, tcod, game),
+            Some(1) => sell(keeper, tcod, game),
+            Some(2) if smith => repair(keeper, tcod, game),
+            _ => break
+        }
+    }
+}
+
+fn buy(keeper: &mut DisplayObj, tcod: &mut Tcod, game: &mut Game) {
+    let shop = keeper.shop.as_mut().unwrap();
+    if shop.stock.is_empty() {
+        msgbox("\n Sold out, come back another time. \n", SHOP_WIDTH, &mut tcod.root, &game.game_settings);
+        return;
+    }
+
+    let options: Vec<(String, Color)> = shop.stock
+        .iter()
+        .map(|item| (format!("{} ({} gold)", item.display_name(), item.value), item.rarity.color()))
+        .collect();
+    let header = format!("What would you like to buy? You have {} gold.\n", game.gold);
+    let stock_id = match colored_menu(&header, &options, SHOP_WIDTH, &mut tcod.root, &game.game_settings) {
+        Some(stock_id) => stock_id,
+        None => return
+    };
+
+    let price = shop.stock[stock_id].value;
+    if price > game.gold {
+        game.messages.add(format!("You can't afford the {}.", shop.stock[stock_id].name), RED);
+    } else if game.inventory.len() >= MAX_INV_SPACE {
+        game.messages.add("Your inventory is full.", RED);
+    } else {
+        let item = shop.stock.remove(stock_id);
+        game.gold -= price;
+        game.messages.add(format!("You buy a {} for {} gold.", item.name, price), GREEN);
+        game.inventory.push(item);
+    }
+}
+
+fn sell(keeper: &mut DisplayObj, tcod: &mut Tcod, game: &mut Game) {
+    let inventory_id = inventory_menu(
+        &game.inventory,
+        "Choose an item to sell, or any other key to cancel.\n",
+        &mut tcod.root,
+        game
+    );
+    let inventory_id = match inventory_id {
+        Some(inventory_id) => inventory_id,
+        None => return
+    };
+
+    let price = sell_price(&game.inventory[inventory_id]);
+    if price == 0 {
+        game.messages.add(format!("The {} has no use for that.", keeper.name), LIGHT_GREY);
+        return;
+    }
+
+    if game.inventory[inventory_id].equipment.is_some() && !game.inventory[inventory_id].dequip(&mut game.messages) {
+        return;
+    }
+    let item = game.inventory.remove(inventory_id);
+    game.gold += price;
+    game.messages.add(format!("You sell the {} for {} gold.", item.name, price), GREEN);
+    // a full shop makes room by getting rid of what it has had longest
+    let stock = &mut keeper.shop.as_mut().unwrap().stock;
+    if stock.len() >= MAX_SHOP_STOCK {
+        stock.remove(0);
+    }
+    stock.push(item);
+}
+
+fn repair(keeper: &DisplayObj, tcod: &mut Tcod, game: &mut Game) {
+    let inventory_id = inventory_menu(
+        &game.inventory,
+        "Choose an item to repair, or any other key to cancel.\n",
+        &mut tcod.root,
+        game
+    );
+    let inventory_id = match inventory_id {
+        Some(inventory_id) => inventory_id,
+        None => return
+    };
+
+    let item = &mut game.inventory[inventory_id];
+    let equipment = match item.equipment.as_mut() {
+        Some(equipment) if equipment.durability < equipment.max_durability => equipment,
+        _ => {
+            game.messages.add(format!("The {} sees nothing to fix on the {}.", keeper.name, item.name), LIGHT_GREY);
+            return;
+        }
+    };
+
+    let price = (equipment.max_durability - equipment.durability) * REPAIR_COST;
+    if price > game.gold {
+        game.messages.add(format!("Repairing the {} costs {} gold, more than you have.", item.name, price), RED);
+    } else {
+        equipment.durability = equipment.max_durability;
+        game.gold -= price;
+        game.messages.add(format!("The {} repairs your {} for {} gold.", keeper.name, item.name, price), GREEN);
+    }
+}
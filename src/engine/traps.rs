@@ -0,0 +1,195 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tcod::colors::*;
+
+use super::random;
+use super::{choose_from_table, is_blocked, next_level, set_tile, DisplayObj, Game, TileKind, Tcod, PLAYER_ID};
+
+const PIT_DAMAGE: i32 = 5;
+const ALARM_TURNS: i32 = 20;
+
+// searching: how far the player looks and the percent chance to notice each hidden thing
+const SEARCH_RADIUS: i32 = 2;
+const SEARCH_CHANCE: u32 = 60;
+const PASSIVE_SEARCH_RADIUS: i32 = 1;
+const PASSIVE_SEARCH_CHANCE: u32 = 10;
+
+// disarming: percent chance to succeed, plus a bonus per player level
+const DISARM_CHANCE: u32 = 55;
+const DISARM_LEVEL_BONUS: u32 = 5;
+
+/// What happens when something springs a trap.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Trap {
+    /// drops the player to the level below
+    Pit,
+    Dart { damage: i32 },
+    /// wakes up every monster on the level
+    Alarm,
+    /// calls up monsters from the level's spawn table
+    Summon { count: u32 }
+}
+
+impl Trap {
+    /// whether the trap is used up after going off once
+    fn single_use(&self) -> bool {
+        matches!(self, Trap::Alarm | Trap::Summon { .. })
+    }
+}
+
+/// Set off any trap under the player after they step onto a new tile.
+pub fn spring_traps(tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<DisplayObj>) {
+    let trap_id = objects
+        .iter()
+        .position(|object| object.trap.is_some() && object.get_pos() == objects[PLAYER_ID].get_pos());
+    let trap_id = match trap_id {
+        Some(trap_id) => trap_id,
+        None => return
+    };
+
+    let trap = objects[trap_id].trap.unwrap();
+    reveal(&mut objects[trap_id]);
+    game.messages.add(format!("You trigger a {}!", objects[trap_id].name), ORANGE);
+    if trap.single_use() {
+        objects.swap_remove(trap_id);
+    }
+    trigger(trap, tcod, game, objects);
+}
+
+fn trigger(trap: Trap, tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<DisplayObj>) {
+    match trap {
+        // there's nothing below the deepest level to fall into
+        Trap::Pit if game.dungeon_level >= game.game_settings.final_level => {
+            game.messages.add("The floor gives way, but you land on solid rock just below.", ORANGE);
+            objects[PLAYER_ID].take_damage(PIT_DAMAGE, game);
+        }
+        Trap::Pit => {
+            game.messages.add("The floor gives way and you fall to the level below!", RED);
+            objects[PLAYER_ID].take_damage(PIT_DAMAGE, game);
+            if objects[PLAYER_ID].alive {
+                next_level(tcod, game, objects);
+            }
+        }
+        Trap::Dart { damage } => {
+            game.messages.add(format!("A poisoned dart hits you for {} hit points.", damage), RED);
+            objects[PLAYER_ID].take_damage(damage, game);
+        }
+        Trap::Alarm => {
+            game.messages.add("A piercing shriek echoes through the crypt!", YELLOW);
+            for object in objects.iter_mut() {
+                object.ai = object.ai.take().map(|ai| ai.alerted(ALARM_TURNS));
+            }
+        }
+        Trap::Summon { count } => {
+            game.messages.add("The runes flare up and something claws its way out!", LIGHT_VIOLET);
+            summon_monsters(count, tcod, game, objects);
+        }
+    }
+}
+
+/// spawn monsters from the level's spawn table on free tiles around the player
+fn summon_monsters(count: u32, tcod: &Tcod, game: &Game, objects: &mut Vec<DisplayObj>) {
+    let tables = match tcod.tables.as_ref() {
+        Some(tables) => tables,
+        None => return
+    };
+    let (player_x, player_y) = objects[PLAYER_ID].get_pos();
+
+    let mut summoned = 0;
+    for dx in -2..3 {
+        for dy in -2..3 {
+            let (x, y) = (player_x + dx, player_y + dy);
+            if summoned >= count || x < 0 || y < 0
+                    || x >= game.game_settings.map_w || y >= game.game_settings.map_h {
+                continue;
+            }
+            if is_blocked(x, y, &game.map, objects) || game.map[x as usize][y as usize].kind.is_hazard() {
+                continue;
+            }
+            if let Some(monster) = choose_from_table(&tables.monsters, game.dungeon_level) {
+                objects.push(monster.as_object(x, y));
+                summoned += 1;
+            }
+        }
+    }
+}
+
+fn reveal(object: &mut DisplayObj) {
+    object.hidden = false;
+    object.always_visible = true;
+}
+
+/// Look for hidden traps and secret doors around the player, noticing each
+/// one with the given percent chance. Returns whether anything was found.
+pub fn search(radius: i32, chance: u32, tcod: &mut Tcod, game: &mut Game, objects: &mut [DisplayObj]) -> bool {
+    let (player_x, player_y) = objects[PLAYER_ID].get_pos();
+    let mut rng = random::rng();
+    let mut found = false;
+
+    for object in objects.iter_mut() {
+        if object.hidden && object.trap.is_some() && object.distance(player_x, player_y) <= radius as f32
+                && rng.gen_range(0..100) < chance {
+            reveal(object);
+            game.messages.add(format!("You spot a {}!", object.name), LIGHT_YELLOW);
+            found = true;
+        }
+    }
+
+    for x in player_x - radius..player_x + radius + 1 {
+        for y in player_y - radius..player_y + radius + 1 {
+            if x < 0 || y < 0 || x >= game.game_settings.map_w || y >= game.game_settings.map_h {
+                continue;
+            }
+            if game.map[x as usize][y as usize].kind == TileKind::SecretDoor && rng.gen_range(0..100) < chance {
+                set_tile(x, y, TileKind::DoorClosed, tcod, &mut game.map);
+                game.messages.add("You find a secret door!", LIGHT_YELLOW);
+                found = true;
+            }
+        }
+    }
+    found
+}
+
+/// the player searches on purpose, taking their time
+pub fn search_actively(tcod: &mut Tcod, game: &mut Game, objects: &mut [DisplayObj]) {
+    if !search(SEARCH_RADIUS, SEARCH_CHANCE, tcod, game, objects) {
+        game.messages.add("You search around but find nothing.", LIGHT_GREY);
+    }
+}
+
+/// a quick glance around, done every turn
+pub fn search_passively(tcod: &mut Tcod, game: &mut Game, objects: &mut [DisplayObj]) {
+    search(PASSIVE_SEARCH_RADIUS, PASSIVE_SEARCH_CHANCE, tcod, game, objects);
+}
+
+/// Try to disarm a known trap next to the player. A failed attempt may set it off.
+/// Returns false if there was nothing to disarm.
+pub fn disarm(tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<DisplayObj>) -> bool {
+    let trap_id = objects.iter().position(|object| {
+        object.trap.is_some() && !object.hidden && object.distance_to(&objects[PLAYER_ID]) < 2.0
+    });
+    let trap_id = match trap_id {
+        Some(trap_id) => trap_id,
+        None => {
+            game.messages.add("There is no known trap next to you.", LIGHT_GREY);
+            return false;
+        }
+    };
+
+    let mut rng = random::rng();
+    let chance = DISARM_CHANCE + objects[PLAYER_ID].level * DISARM_LEVEL_BONUS;
+    if rng.gen_range(0..100) < chance {
+        let trap = objects.swap_remove(trap_id);
+        game.messages.add(format!("You disarm the {}.", trap.name), LIGHT_GREEN);
+    } else if rng.gen() {
+        let trap = objects[trap_id].trap.unwrap();
+        game.messages.add(format!("You fumble and set off the {}!", objects[trap_id].name), ORANGE);
+        if trap.single_use() {
+            objects.swap_remove(trap_id);
+        }
+        trigger(trap, tcod, game, objects);
+    } else {
+        game.messages.add(format!("You fail to disarm the {}.", objects[trap_id].name), LIGHT_GREY);
+    }
+    true
+}
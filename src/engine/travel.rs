@@ -0,0 +1,67 @@
+use std::collections::VecDeque;
+
+use super::{DisplayObj, Game, TileKind, PLAYER_ID};
+
+/// Whether the player can safely walk across a tile they know about: no walls,
+/// locked doors or lava, nothing in the way and no trap they've spotted. A
+/// secret door still looks like wall, so routes mustn't give it away.
+fn is_walkable(x: i32, y: i32, game: &Game, objects: &[DisplayObj]) -> bool {
+    let tile = &game.map[x as usize][y as usize];
+    tile.explored
+        && tile.kind.is_traversable()
+        && tile.kind != TileKind::SecretDoor
+        && !objects.iter().any(|object| {
+            object.get_pos() == (x, y) && (object.blocks || (object.trap.is_some() && !object.hidden))
+        })
+}
+
+/// An explored tile the player could stand on to see more of the level.
+pub fn is_frontier(x: i32, y: i32, game: &Game) -> bool {
+    let (width, height) = (game.map.len() as i32, game.map[0].len() as i32);
+    (-1..=1).any(|dx| (-1..=1).any(|dy| {
+        let (nx, ny) = (x + dx, y + dy);
+        nx >= 0 && ny >= 0 && nx < width && ny < height && !game.map[nx as usize][ny as usize].explored
+    }))
+}
+
+/// Search outwards from the player over the tiles they know, and return the
+/// first step on the shortest path to the nearest tile that meets the goal.
+pub fn step_towards<F: Fn(i32, i32) -> bool>(game: &Game, objects: &[DisplayObj], goal: F) -> Option<(i32, i32)> {
+    let (width, height) = (game.map.len() as i32, game.map[0].len() as i32);
+    let start = objects[PLAYER_ID].get_pos();
+
+    // for each tile reached, the tile it was reached from
+    let mut came_from = vec![vec![None; height as usize]; width as usize];
+    came_from[start.0 as usize][start.1 as usize] = Some(start);
+    let mut frontier = VecDeque::new();
+    frontier.push_back(start);
+
+    while let Some((x, y)) = frontier.pop_front() {
+        if (x, y) != start && goal(x, y) {
+            // walk back to the tile right next to the player
+            let mut step = (x, y);
+            while let Some(previous) = came_from[step.0 as usize][step.1 as usize] {
+                if previous == start {
+                    return Some((step.0 - start.0, step.1 - start.1));
+                }
+                step = previous;
+            }
+            return None;
+        }
+
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= width || ny >= height
+                        || came_from[nx as usize][ny as usize].is_some() {
+                    continue;
+                }
+                if is_walkable(nx, ny, game, objects) {
+                    came_from[nx as usize][ny as usize] = Some((x, y));
+                    frontier.push_back((nx, ny));
+                }
+            }
+        }
+    }
+    None
+}
@@ -1,9 +1,61 @@
 use tcod::input::Mouse;
-use tcod::{colors::*, Map as FovMap, TextAlignment};
+use tcod::{colors::*, TextAlignment};
 use tcod::console::{blit, Offscreen, Root};
 use tcod::{BackgroundFlag, Color, Console};
+use std::cmp;
 
 use super::{init_fov, load_game, new_game, play_game, DisplayObj, Game, GameSettings, Tcod, FOV_ALGO, INVENTORY_WIDTH, PLAYER_ID};
+use super::hunger::Hunger;
+use super::town::TOWN_LEVEL;
+use super::{Ending, RunRecord};
+
+const ENDING_SCREEN_WIDTH: i32 = 50;
+
+/// Tracks which window of the map is drawn on screen, so the map can be
+/// larger than the console.
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32
+}
+
+impl Camera {
+    pub fn new(width: i32, height: i32) -> Self {
+        Camera {
+            x: 0,
+            y: 0,
+            width: width,
+            height: height
+        }
+    }
+
+    /// center the camera on a map position, without scrolling past the map edges
+    pub fn follow(&mut self, x: i32, y: i32, map_w: i32, map_h: i32) {
+        self.x = cmp::max(0, cmp::min(x - self.width / 2, map_w - self.width));
+        self.y = cmp::max(0, cmp::min(y - self.height / 2, map_h - self.height));
+    }
+
+    /// convert map coordinates to console coordinates, if they are on screen
+    pub fn to_screen(&self, x: i32, y: i32) -> Option<(i32, i32)> {
+        let (screen_x, screen_y) = (x - self.x, y - self.y);
+        if screen_x >= 0 && screen_x < self.width && screen_y >= 0 && screen_y < self.height {
+            Some((screen_x, screen_y))
+        } else {
+            None
+        }
+    }
+
+    /// convert console coordinates to map coordinates, if they are inside the view
+    pub fn to_world(&self, screen_x: i32, screen_y: i32) -> Option<(i32, i32)> {
+        if screen_x >= 0 && screen_x < self.width && screen_y >= 0 && screen_y < self.height {
+            Some((screen_x + self.x, screen_y + self.y))
+        } else {
+            None
+        }
+    }
+}
 
 pub fn main_menu(tcod: &mut Tcod, game_settings: &GameSettings) {
     let img = tcod::image::Image::from_file("assets/menu_background.png")
@@ -28,11 +80,19 @@ pub fn main_menu(tcod: &mut Tcod, game_settings: &GameSettings) {
                 "Created By: JustisGames"
             );
 
+        if !tcod.mods.is_empty() {
+            tcod.root.print_ex(game_settings.screen_w / 2, game_settings.screen_h - 4, 
+                    BackgroundFlag::None, 
+                    TextAlignment::Center, 
+                    format!("Mods: {}", tcod.mods.join(", "))
+                );
+        }
+
         let choice = menu("", choices, 24, &mut tcod.root, game_settings);
 
         match choice {
             Some(0) => {
-                let (mut game, mut objects) = new_game(tcod, *game_settings);
+                let (mut game, mut objects) = new_game(tcod, game_settings.clone());
 
                 play_game(tcod, &mut game, &mut objects);
             }
@@ -42,8 +102,8 @@ pub fn main_menu(tcod: &mut Tcod, game_settings: &GameSettings) {
                         init_fov(tcod, &game);
                         play_game(tcod, &mut game, &mut objects);
                     }
-                    Err(_e) => {
-                        msgbox("\n No saved game to load. \n", 24, &mut tcod.root, &game_settings);
+                    Err(e) => {
+                        msgbox(&format!("\n {} \n", e), 30, &mut tcod.root, &game_settings);
                         continue;
                     }
                 }
@@ -91,66 +151,85 @@ fn render_bar(
     );
 }
 
+/// Work out what the player can see from where they stand, and what's lit.
+pub fn compute_fov(tcod: &mut Tcod, game: &Game, objects: &[DisplayObj]) {
+    // line of sight reaches further than the torch; what's actually seen depends on the light
+    tcod.fov
+        .compute_fov(objects[PLAYER_ID].x, objects[PLAYER_ID].y, 
+            game.game_settings.sight_radius, 
+            game.game_settings.fov_light_walls, 
+            FOV_ALGO);
+    tcod.lighting.compute(&mut tcod.light_fov, objects);
+}
+
 pub fn render_all(tcod: &mut Tcod, game: &mut Game, objects: &[DisplayObj], fov_recompute: bool){
     if fov_recompute {
-        tcod.fov
-            .compute_fov(objects[PLAYER_ID].x, objects[PLAYER_ID].y, 
-                game.game_settings.torch_radius, 
-                game.game_settings.fov_light_walls, 
-                FOV_ALGO);
+        compute_fov(tcod, game, objects);
     }
 
-    let mut to_draw: Vec<_> = objects.iter()
-        .filter(|o| {
-            tcod.fov.is_in_fov(o.x, o.y) 
-                || (o.always_visible && game.map[o.x as usize][o.y as usize].explored)
-        })
-        .collect();
-    // sort so that non-blocking objects come first
-    to_draw.sort_by(|o1, o2| { o1.blocks.cmp(&o2.blocks) });
-    // draw the objects in the list
-    for object in &to_draw {
-        object.draw(&mut tcod.con);
-    }
+    let (player_x, player_y) = objects[PLAYER_ID].get_pos();
+    tcod.camera.follow(player_x, player_y, game.game_settings.map_w, game.game_settings.map_h);
 
-    for y in 0..game.game_settings.map_h
-    {
-        for x in 0..game.game_settings.map_w{
-            let visible = tcod.fov.is_in_fov(x, y);
-            let wall = game.map[x as usize][y as usize].block_sight;
-            let color = match (visible, wall) {
-                (false, true) => game.game_settings.dark_wall_color,
-                (false, false) => game.game_settings.dark_ground_color,
-                (true, true) => game.game_settings.light_wall_color,
-                (true, false) => game.game_settings.light_ground_color
+    for screen_y in 0..tcod.camera.height {
+        for screen_x in 0..tcod.camera.width {
+            let (x, y) = match tcod.camera.to_world(screen_x, screen_y) {
+                Some((x, y)) if x < game.game_settings.map_w && y < game.game_settings.map_h => (x, y),
+                _ => continue
             };
-
-            let explored = &mut game.map[x as usize][y as usize].explored;
+            let visible = tcod.can_see(x, y);
+            let tile = &mut game.map[x as usize][y as usize];
             if visible {
-                *explored = true;
+                tile.explored = true;
             }
 
-            if *explored {
-                tcod.con
-                    .set_char_background(x, y, color, BackgroundFlag::Set);
+            if tile.explored {
+                let dark_background = tile.kind.background(&game.game_settings, false);
+                let background = if visible {
+                    let lit_background = tile.kind.background(&game.game_settings, true);
+                    tcod.lighting.shade(dark_background, lit_background, x, y)
+                } else {
+                    dark_background
+                };
+                match tile.kind.glyph() {
+                    Some((glyph, color)) => {
+                        let color = if visible { tcod.lighting.shade(DARKER_GREY, color, x, y) } else { DARKER_GREY };
+                        tcod.con.put_char_ex(screen_x, screen_y, glyph, color, background);
+                    }
+                    None => {
+                        tcod.con.set_char_background(screen_x, screen_y, background, BackgroundFlag::Set);
+                    }
+                }
             }
-
         }
     }
 
+    let mut to_draw: Vec<_> = objects.iter()
+        .filter(|o| {
+            !o.hidden && (tcod.can_see(o.x, o.y) 
+                || (o.always_visible && game.map[o.x as usize][o.y as usize].explored))
+        })
+        .collect();
+    // sort so that non-blocking objects come first
+    to_draw.sort_by(|o1, o2| { o1.blocks.cmp(&o2.blocks) });
+    // draw the objects in the list, over the terrain
+    for object in &to_draw {
+        object.draw(&mut tcod.con, &tcod.camera);
+    }
+
     // show the player's stats
     // prepare to render the GUI panel
     tcod.panel.set_default_background(BLACK);
     tcod.panel.clear();
 
     // display names of objects under the mouse
+    let names = get_names_under_mouse(tcod.mouse, objects, tcod);
     tcod.panel.set_default_foreground(LIGHT_GREY);
     tcod.panel.print_ex(
         1,
         0,
         BackgroundFlag::None,
         TextAlignment::Left,
-        get_names_under_mouse(tcod.mouse, objects, &tcod.fov),
+        names,
     );
 
     // show the player's stats
@@ -173,9 +252,26 @@ pub fn render_all(tcod: &mut Tcod, game: &mut Game, objects: &[DisplayObj], fov_
         3,
         BackgroundFlag::None,
         TextAlignment::Left,
-        format!("Dungeon level: {}", game.dungeon_level),
+        if game.dungeon_level == TOWN_LEVEL {
+            "Town".to_string()
+        } else {
+            format!("Dungeon level: {}", game.dungeon_level)
+        },
+    );
+    tcod.panel.print_ex(
+        1,
+        4,
+        BackgroundFlag::None,
+        TextAlignment::Left,
+        format!("Gold: {}", game.gold),
     );
 
+    // and how hungry they are, once it starts to matter
+    let hunger = Hunger::from_satiety(game.satiety);
+    tcod.panel.set_default_foreground(hunger.color());
+    tcod.panel.print_ex(1, 5, BackgroundFlag::None, TextAlignment::Left, hunger.label());
+    tcod.panel.set_default_foreground(WHITE);
+
     // print the game messages, one line at a time
     let mut y = game.game_settings.msg_h as i32;
     for &(ref msg, color) in game.messages.iter().rev() {
@@ -200,27 +296,40 @@ pub fn render_all(tcod: &mut Tcod, game: &mut Game, objects: &[DisplayObj], fov_
     );
 
     //Basically a double buffer
-    blit(&tcod.con, (0, 0), (game.game_settings.map_w, game.game_settings.map_h), &mut tcod.root, (0, 0), 1.0, 1.0);
+    blit(&tcod.con, (0, 0), (tcod.camera.width, tcod.camera.height), &mut tcod.root, (0, 0), 1.0, 1.0);
 }
 
 pub fn inventory_menu(inventory: &[DisplayObj], header: &str, root: &mut Root, game: &Game) -> Option<usize> {
     // how a menu with each item of the inventory as an option
     let options = if inventory.len() == 0 {
-        vec!["Inventory is empty.".into()]
+        vec![("Inventory is empty.".into(), WHITE)]
     } else {
         inventory
             .iter()
             .map(|item| {
-                match item.equipment {
+                let name = match item.equipment {
                     Some(equipment) if equipment.equipped => {
-                        format!("{} (on {})", item.name, equipment.slot)
+                        format!("{} (on {})", item.display_name(), equipment.slot)
                     }
-                    _ => item.name.clone(),
-                }
+                    _ => item.display_name(),
+                };
+                // light sources show how long they'll keep burning
+                let name = match item.equipment.and_then(|e| e.fuel) {
+                    Some(fuel) => format!("{} [{} turns]", name, fuel),
+                    None => name,
+                };
+                // and worn equipment how much wear it can still take
+                let name = match item.equipment.and_then(|e| e.condition()) {
+                    Some(condition) => format!("{} [{}%]", name, condition),
+                    None => name,
+                };
+                // known curses stand out from the rarity colors
+                let color = if item.identified && item.curse.is_some() { RED } else { item.rarity.color() };
+                (name, color)
             }).collect()
     };
 
-    let inventory_index = menu(header, &options, INVENTORY_WIDTH, root, &game.game_settings);
+    let inventory_index = colored_menu(header, &options, INVENTORY_WIDTH, root, &game.game_settings);
 
     // if an item was chosen, return it
     if inventory.len() > 0 {
@@ -231,13 +340,16 @@ pub fn inventory_menu(inventory: &[DisplayObj], header: &str, root: &mut Root, g
 }
 
 /// return a string with the names of all objects under the mouse
-fn get_names_under_mouse(mouse: Mouse, objects: &[DisplayObj], fov_map: &FovMap) -> String {
-    let (x, y) = (mouse.cx as i32, mouse.cy as i32);
+fn get_names_under_mouse(mouse: Mouse, objects: &[DisplayObj], tcod: &Tcod) -> String {
+    let (x, y) = match tcod.camera.to_world(mouse.cx as i32, mouse.cy as i32) {
+        Some(pos) => pos,
+        None => return String::new()
+    };
 
     // create a list with the names of all objects at the mouse's coordinates and in FOV
     let names = objects
         .iter()
-        .filter(|obj| obj.get_pos() == (x, y) && fov_map.is_in_fov(obj.x, obj.y))
+        .filter(|obj| obj.get_pos() == (x, y) && !obj.hidden && tcod.can_see(obj.x, obj.y))
         .map(|obj| obj.name.clone())
         .collect::<Vec<_>>();
 
@@ -249,8 +361,29 @@ pub fn msgbox(text: &str, width: i32, root: &mut Root, game_settings: &GameSetti
     menu(text, options, width, root, game_settings);
 }
 
+/// show how a run ended, and how well it went
+pub fn ending_screen(record: &RunRecord, root: &mut Root, game_settings: &GameSettings) {
+    let text = match record.ending {
+        Ending::Death => "You died in the Tombs of the Ancient Kings.",
+        Ending::Victory => "You return to the surface with the Crown of the Ancient Kings. The crypt's curse is broken!",
+        Ending::Quit => "You turn your back on the crypt, leaving its treasures to the next fool."
+    };
+    let msg = format!(
+        "\n {} \n\n Deepest level: {}\n Character level: {}\n Gold: {}\n\n Score: {}\n",
+        text, record.deepest_level, record.character_level, record.gold, record.score
+    );
+    msgbox(&msg, ENDING_SCREEN_WIDTH, root, game_settings);
+}
+
 pub fn menu<T: AsRef<str>>(header: &str, options: &[T], width: i32, root: &mut Root, 
         game_settings: &GameSettings) -> Option<usize> {
+    let options: Vec<(&str, Color)> = options.iter().map(|option| (option.as_ref(), WHITE)).collect();
+    colored_menu(header, &options, width, root, game_settings)
+}
+
+/// a menu where each option is printed in its own color
+pub fn colored_menu<T: AsRef<str>>(header: &str, options: &[(T, Color)], width: i32, root: &mut Root, 
+        game_settings: &GameSettings) -> Option<usize> {
     assert!(
         options.len() <= 26,
         "Cannot have a menu with more than 26 options."
@@ -280,9 +413,10 @@ pub fn menu<T: AsRef<str>>(header: &str, options: &[T], width: i32, root: &mut R
     );
 
     // print all the options
-    for (index, option_text) in options.iter().enumerate() {
+    for (index, (option_text, color)) in options.iter().enumerate() {
         let menu_letter = (b'a' + index as u8) as char;
         let text = format!("({}) {}", menu_letter, option_text.as_ref());
+        window.set_default_foreground(*color);
         window.print_ex(
             0,
             header_height + index as i32,
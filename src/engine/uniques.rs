@@ -0,0 +1,78 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::affixes;
+use super::random;
+use super::mapgen::Area;
+use super::{free_tiles_around, is_blocked, DisplayObj, Game, Map, Tcod, PACK_RADIUS};
+
+/// Tries at finding a free tile for a unique before giving up on it for this level.
+const PLACEMENT_TRIES: u32 = 20;
+
+/// What became of a unique monster that has turned up in this run.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum UniqueStatus {
+    Alive,
+    Slain
+}
+
+/// Roll for the uniques allowed at this depth and place the ones that turn up,
+/// along with their minions, somewhere away from the player. A unique only
+/// ever appears once per run.
+pub fn spawn_uniques(tcod: &Tcod, game: &mut Game, areas: &[Area], objects: &mut Vec<DisplayObj>) {
+    let tables = match tcod.tables.as_ref() {
+        Some(tables) => tables,
+        None => return
+    };
+    if areas.len() < 2 {
+        return;
+    }
+    let mut rng = random::rng();
+    let level = game.dungeon_level;
+
+    for unique in &tables.uniques {
+        let name = unique.monster.name();
+        if game.uniques.contains_key(name) || level < unique.min_level || level > unique.max_level
+                || rng.gen_range(0..100) >= unique.chance {
+            continue;
+        }
+
+        let (x, y) = match find_free_tile(&areas[1..], &game.map, objects) {
+            Some(pos) => pos,
+            None => continue
+        };
+        let mut monster = unique.monster.as_object(x, y);
+        monster.unique = true;
+        monster.death_message = unique.death_message.clone();
+        monster.loot = unique.loot
+            .iter()
+            .filter_map(|item| tables.find_item(item))
+            .map(|item| {
+                let mut item = item.as_object(x, y);
+                affixes::enchant(&mut item, tables, level);
+                item
+            })
+            .collect();
+        objects.push(monster);
+
+        for group in &unique.minions {
+            if let Some(minion) = tables.find_monster(&group.monster) {
+                for (mx, my) in free_tiles_around(x, y, PACK_RADIUS, group.count, &game.map, objects) {
+                    objects.push(minion.as_object(mx, my));
+                }
+            }
+        }
+        game.uniques.insert(name.to_string(), UniqueStatus::Alive);
+    }
+}
+
+fn find_free_tile(areas: &[Area], map: &Map, objects: &[DisplayObj]) -> Option<(i32, i32)> {
+    let mut rng = random::rng();
+    for _ in 0..PLACEMENT_TRIES {
+        let (x, y) = areas[rng.gen_range(0..areas.len())].random_tile();
+        if !is_blocked(x, y, map, objects) && !map[x as usize][y as usize].kind.is_hazard() {
+            return Some((x, y));
+        }
+    }
+    None
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+
+use super::conf::{LootDrop, ObjectConfiguration, Pack, Transition, TransitionTables};
+use super::mods::DataPack;
+
+/// What a check of the settings file turned up. Errors keep it from being
+/// used; warnings point at data that works, but probably not as intended.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Finds where entries are in the settings file or the mods, so problems can
+/// point at a line.
+struct Locator<'a> {
+    packs: &'a [DataPack]
+}
+
+impl<'a> Locator<'a> {
+    /// "file:line" for the nth entry with this name in a section, looking
+    /// through the last loaded packs first, or just the base file if it
+    /// can't be found
+    fn at(&self, section: &str, name: &str, nth: usize) -> String {
+        self.packs
+            .iter()
+            .rev()
+            .find_map(|pack| line(&pack.json, section, name, nth).map(|line| format!("{}:{}", pack.path, line)))
+            .unwrap_or_else(|| self.section(section))
+    }
+
+    /// "file:line" of a section in the last pack that sets it
+    fn section(&self, section: &str) -> String {
+        let key = format!("\"{}\"", section);
+        self.packs
+            .iter()
+            .rev()
+            .find_map(|pack| pack.json.find(&key).map(|offset| {
+                format!("{}:{}", pack.path, pack.json[..offset + key.len()].lines().count())
+            }))
+            .or_else(|| self.packs.first().map(|pack| pack.path.clone()))
+            .unwrap_or_default()
+    }
+}
+
+fn line(json: &str, section: &str, name: &str, nth: usize) -> Option<usize> {
+    let start = json.find(&format!("\"{}\"", section))?;
+    let quoted_name = format!("\"{}\"", name);
+    let offset = json[start..]
+        .match_indices("\"name\"")
+        .map(|(index, key)| start + index + key.len())
+        .filter(|&after_key| {
+            let rest = json[after_key..].trim_start();
+            rest.starts_with(':') && rest[1..].trim_start().starts_with(&quoted_name)
+        })
+        .nth(nth)?;
+    Some(json[..offset].lines().count())
+}
+
+/// Check the spawn tables for the mistakes serde lets through: things that
+/// would only go wrong in the middle of a game, or quietly not work at all.
+pub fn check_tables(tables: &TransitionTables, packs: &[DataPack]) -> Report {
+    let locator = Locator { packs: packs };
+    let mut report = Report::default();
+
+    for (name, levels) in [
+        ("max_monsters", &tables.max_monsters),
+        ("max_items", &tables.max_items),
+        ("max_traps", &tables.max_traps),
+        ("max_features", &tables.max_features),
+        ("curse_chance", &tables.curse_chance)
+    ].iter() {
+        check_transitions(levels, &format!("{}: {}", locator.section(name), name), &mut report);
+    }
+
+    let sections = [
+        ("monsters", &tables.monsters),
+        ("items", &tables.items),
+        ("traps", &tables.traps),
+        ("features", &tables.features)
+    ];
+    for (section, objects) in sections.iter() {
+        let names: Vec<&str> = objects.iter().map(|object| object.name()).collect();
+        check_duplicates(section, &names, &locator, &mut report);
+
+        for (index, object) in objects.iter().enumerate() {
+            let at = locator.at(section, object.name(), occurrence(&names, index));
+            check_object(section, object, &at, tables, &mut report);
+        }
+    }
+
+    let names: Vec<&str> = tables.uniques.iter().map(|unique| unique.monster.name()).collect();
+    check_duplicates("uniques", &names, &locator, &mut report);
+    for (index, unique) in tables.uniques.iter().enumerate() {
+        let name = unique.monster.name();
+        let at = locator.at("uniques", name, occurrence(&names, index));
+        check_object("monsters", &unique.monster, &at, tables, &mut report);
+        if unique.min_level > unique.max_level {
+            report.errors.push(format!(
+                "{}: unique '{}' has min_level {} above max_level {}",
+                at, name, unique.min_level, unique.max_level
+            ));
+        }
+        if unique.chance > 100 {
+            report.warnings.push(format!("{}: unique '{}' has a chance of {}%, over 100", at, name, unique.chance));
+        }
+        for item in &unique.loot {
+            if tables.find_item(item).is_none() {
+                report.errors.push(format!("{}: unique '{}' carries unknown item '{}'", at, name, item));
+            }
+        }
+        for group in &unique.minions {
+            if tables.find_monster(&group.monster).is_none() {
+                report.errors.push(format!("{}: unique '{}' has unknown minion '{}'", at, name, group.monster));
+            }
+        }
+    }
+
+    let names: Vec<&str> = tables.loot_tables.iter().map(|table| table.name.as_str()).collect();
+    check_duplicates("loot_tables", &names, &locator, &mut report);
+    for (index, table) in tables.loot_tables.iter().enumerate() {
+        let at = locator.at("loot_tables", &table.name, occurrence(&names, index));
+        for entry in &table.entries {
+            match (entry.item.as_ref(), entry.table.as_ref()) {
+                (Some(item), None) if tables.find_item(item).is_none() => report.errors.push(format!(
+                    "{}: loot table '{}' has unknown item '{}'", at, table.name, item
+                )),
+                (None, Some(nested)) if tables.find_loot_table(nested).is_none() => report.errors.push(format!(
+                    "{}: loot table '{}' has unknown loot table '{}'", at, table.name, nested
+                )),
+                (Some(_), Some(_)) | (None, None) => report.errors.push(format!(
+                    "{}: every entry of loot table '{}' needs either an item or a table", at, table.name
+                )),
+                _ => {}
+            }
+            check_transitions(&entry.weight, &format!("{}: loot table '{}'", at, table.name), &mut report);
+        }
+        if table.entries.iter().all(|entry| entry.weight.iter().all(|weight| weight.value == 0)) {
+            report.warnings.push(format!("{}: loot table '{}' can never roll anything, all its weights are 0", at, table.name));
+        }
+    }
+
+    let names: Vec<&str> = tables.affixes.iter().map(|affix| affix.name.as_str()).collect();
+    check_duplicates("affixes", &names, &locator, &mut report);
+    for affix in &tables.affixes {
+        check_transitions(&affix.weight, &format!("{}: affix '{}'", locator.at("affixes", &affix.name, 0), affix.name), &mut report);
+    }
+    for rarity in &tables.rarities {
+        check_transitions(&rarity.weight, &format!("{}: rarity {:?}", locator.section("rarities"), rarity.rarity), &mut report);
+    }
+
+    let names: Vec<&str> = tables.vaults.iter().map(|vault| vault.name.as_str()).collect();
+    check_duplicates("vaults", &names, &locator, &mut report);
+    for vault in &tables.vaults {
+        let at = locator.at("vaults", &vault.name, 0);
+        if vault.min_level > vault.max_level {
+            report.errors.push(format!(
+                "{}: vault '{}' has min_level {} above max_level {}",
+                at, vault.name, vault.min_level, vault.max_level
+            ));
+        }
+        for legend in &vault.legend {
+            if let Some(monster) = legend.monster.as_ref().filter(|monster| tables.find_monster(monster).is_none()) {
+                report.errors.push(format!("{}: vault '{}' places unknown monster '{}'", at, vault.name, monster));
+            }
+            if let Some(item) = legend.item.as_ref().filter(|item| tables.find_item(item).is_none()) {
+                report.errors.push(format!("{}: vault '{}' places unknown item '{}'", at, vault.name, item));
+            }
+        }
+    }
+
+    report
+}
+
+/// which of the entries sharing its name an entry is
+fn occurrence(names: &[&str], index: usize) -> usize {
+    names[..index].iter().filter(|&&name| name == names[index]).count()
+}
+
+/// Lookups by name only ever find the first of several entries with the same
+/// name, and spawn tables roll each of them, so duplicates are worth a warning.
+fn check_duplicates(section: &str, names: &[&str], locator: &Locator, report: &mut Report) {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for name in names {
+        *counts.entry(name).or_insert(0) += 1;
+        if counts[name] == 2 {
+            report.warnings.push(format!(
+                "{}: '{}' appears more than once in {}, also at {}",
+                locator.at(section, name, 1), name, section, locator.at(section, name, 0)
+            ));
+        }
+    }
+}
+
+/// levels have to go up, as the value for a depth comes from the last level reached
+fn check_transitions(transitions: &[Transition], at: &str, report: &mut Report) {
+    if transitions.windows(2).any(|pair| pair[0].level >= pair[1].level) {
+        report.warnings.push(format!("{}: levels should be listed in increasing order", at));
+    }
+}
+
+fn check_object(section: &str, object: &ObjectConfiguration, at: &str, tables: &TransitionTables, report: &mut Report) {
+    let name = object.name();
+    match section {
+        "monsters" => {
+            if object.fighter.is_none() {
+                report.errors.push(format!("{}: monster '{}' has no fighter", at, name));
+            }
+            if object.item.is_some() || object.equipment.is_some() {
+                report.errors.push(format!("{}: monster '{}' has an \"item\" or \"equipment\" set, monsters can't be picked up", at, name));
+            }
+        }
+        "items" => {
+            if object.item.is_none() {
+                report.errors.push(format!("{}: item '{}' has no \"item\" kind", at, name));
+            }
+            if object.fighter.is_some() {
+                report.errors.push(format!("{}: item '{}' has a fighter, items can't fight", at, name));
+            }
+        }
+        "traps" if object.trap.is_none() => {
+            report.errors.push(format!("{}: trap '{}' has no \"trap\" set", at, name));
+        }
+        _ => {}
+    }
+
+    check_transitions(&object.transition_table, &format!("{}: '{}'", at, name), report);
+    if let Some(pack) = object.pack.as_ref() {
+        check_pack(pack, name, at, tables, report);
+    }
+    if let Some(drop) = object.drops.as_ref() {
+        check_drop(drop, name, at, tables, report);
+    }
+}
+
+fn check_pack(pack: &Pack, name: &str, at: &str, tables: &TransitionTables, report: &mut Report) {
+    if pack.min_size < 1 || pack.min_size > pack.max_size {
+        report.errors.push(format!(
+            "{}: the pack of '{}' must have a min_size of at least 1 and no larger than its max_size",
+            at, name
+        ));
+    }
+    if let Some(followers) = pack.followers.as_ref().filter(|followers| tables.find_monster(followers).is_none()) {
+        report.errors.push(format!("{}: the pack of '{}' has unknown followers '{}'", at, name, followers));
+    }
+}
+
+fn check_drop(drop: &LootDrop, name: &str, at: &str, tables: &TransitionTables, report: &mut Report) {
+    if tables.find_loot_table(&drop.table).is_none() {
+        report.errors.push(format!("{}: '{}' drops from unknown loot table '{}'", at, name, drop.table));
+    }
+    check_transitions(&drop.chance, &format!("{}: drops of '{}'", at, name), report);
+}
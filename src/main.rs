@@ -1,15 +1,71 @@
+use std::env;
+use std::process;
+
 use roguelike_tut::engine::*;
-use roguelike_tut::engine::conf::load_weighted_tables;
+use roguelike_tut::engine::conf::{check_data_packs, load_game_settings, load_weighted_tables, Options};
+use roguelike_tut::engine::keymap::load_keymap;
+use roguelike_tut::engine::mods::load_data_packs;
+use roguelike_tut::engine::validation::Report;
 use ui::{main_menu, msgbox};
 
+const USAGE: &str = "Usage: necroventure [--settings <file>] [--seed <number>] [--font <file>] [--check-config]";
+
 fn main() {
-    let game_settings = GameSettings::new();
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}\n{}", e, USAGE);
+            return;
+        }
+    };
+
+    if options.check_config {
+        process::exit(check_config(&options));
+    }
+
+    let packs = match load_data_packs(&options.settings_file) {
+        Ok((packs, problems)) => {
+            for problem in problems {
+                println!("Skipping mod: {}", problem);
+            }
+            packs
+        }
+        Err(e) => {
+            println!("{}", e);
+            vec![]
+        }
+    };
+
+    let mut game_settings = match load_game_settings(&packs) {
+        Ok(game_settings) => game_settings,
+        Err(e) => {
+            println!("{}", e);
+            GameSettings::new()
+        }
+    };
+    options.apply(&mut game_settings);
+    if let Err(e) = game_settings.validate() {
+        println!("Invalid game settings:\n{}", e);
+        return;
+    }
+
+    let tables = load_weighted_tables(&packs);
+    if let Ok((table, _)) = &tables {
+        if let Err(e) = check_generators(table, &game_settings) {
+            println!("Invalid game settings:\n{}", e);
+            return;
+        }
+    }
  
     let mut tcod = init_tcod(&game_settings);
 
-    match load_weighted_tables() {
-        Ok(table) => {
+    match tables {
+        Ok((table, warnings)) => {
+            for warning in warnings {
+                println!("warning: {}", warning);
+            }
             tcod.tables = Some(table);
+            tcod.mods = packs[1..].iter().map(|pack| pack.name.clone()).collect();
         }
         Err(e) => {
             println!("{}", e);
@@ -17,5 +73,37 @@ fn main() {
         }
     }
 
+    match load_keymap() {
+        Ok(keymap) => tcod.keymap = keymap,
+        Err(e) => println!("Invalid keymap, using the default keys: {}", e)
+    }
+
     main_menu(&mut tcod, &game_settings);
+}
+
+/// Check the data files and print what's wrong with them, returning the exit code.
+fn check_config(options: &Options) -> i32 {
+    let mut report = match load_data_packs(&options.settings_file) {
+        Ok((packs, problems)) => {
+            let mut report = check_data_packs(&packs);
+            report.errors.extend(problems);
+            report
+        }
+        Err(e) => Report { errors: vec![e.to_string()], warnings: vec![] }
+    };
+    if let Err(e) = load_keymap() {
+        report.errors.push(format!("keymap: {}", e));
+    }
+
+    for warning in &report.warnings {
+        println!("warning: {}", warning);
+    }
+    for error in &report.errors {
+        println!("error: {}", error);
+    }
+    println!(
+        "{}: {} error(s), {} warning(s)",
+        options.settings_file, report.errors.len(), report.warnings.len()
+    );
+    if report.is_ok() { 0 } else { 1 }
 }
\ No newline at end of file